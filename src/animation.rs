@@ -0,0 +1,306 @@
+//! Keyframe animation of sprites and meshes
+//!
+//! A `Clip` is a sequence of poses at given moments, such as the frames of a
+//! walk cycle. An `Animator` plays the clip of the state an entity is in,
+//! fades from one clip to another when the state changes, and moves on to the
+//! next state on its own when a clip which is played once is over. Times are
+//! in seconds.
+
+use geometry::{Shape, Vector3};
+
+/// Something a clip is made of
+pub trait Pose: Clone {
+/// The pose `t` of the way from `self` to `other`, `t` being from 0 to 1.
+    fn blend(&self, other: &Self, t: f64) -> Self;
+}
+
+/// A frame of a sprite atlas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteFrame(pub usize);
+
+impl Pose for SpriteFrame {
+    /// Frames can't be mixed, so the closest one is taken.
+    fn blend(&self, other: &SpriteFrame, t: f64) -> SpriteFrame {
+        if t < 0.5 { *self } else { *other }
+    }
+}
+
+/// The positions of all the vertices of a mesh.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshPose(pub Vec<Vector3<f64>>);
+
+impl Pose for MeshPose {
+    fn blend(&self, other: &MeshPose, t: f64) -> MeshPose {
+        assert_eq!(self.0.len(), other.0.len());
+        MeshPose(self.0.iter().zip(other.0.iter()).map(
+            |(&a, &b)| a + (b - a) * t).collect())
+    }
+}
+
+impl MeshPose {
+    pub fn of(shape: &Shape) -> MeshPose {
+        MeshPose(shape.verts.iter().map(|v| v.coords).collect())
+    }
+
+/// A copy of a shape with its vertices moved to the pose.
+    pub fn apply(&self, shape: &Shape) -> Shape {
+        assert_eq!(self.0.len(), shape.verts.len());
+        let mut verts = shape.verts.clone();
+        for (v, &p) in verts.iter_mut().zip(self.0.iter()) {
+            v.coords = p;
+        }
+        Shape {
+            verts: verts,
+            primitive: shape.primitive,
+            material: shape.material.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Playback {
+    /// Starts over when it's over.
+    Loop,
+    /// Stops at the last keyframe.
+    Once,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Every keyframe is held until the next one.
+    Step,
+    /// The poses between keyframes are blended.
+    Linear,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keyframe<P> {
+    pub time: f64,
+    pub pose: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip<P> {
+    keyframes: Vec<Keyframe<P>>,
+    duration: f64,
+    playback: Playback,
+    interpolation: Interpolation,
+}
+
+impl<P: Pose> Clip<P> {
+/// A clip of the given keyframes
+///
+/// The keyframes have to be in the order of their time. A looped clip goes
+/// from the last keyframe back to the first one at `duration`.
+    pub fn new(keyframes: Vec<Keyframe<P>>, duration: f64, playback: Playback,
+               interpolation: Interpolation) -> Clip<P> {
+        assert!(!keyframes.is_empty());
+        assert!(keyframes.windows(2).all(|k| k[0].time <= k[1].time));
+        Clip {
+            keyframes: keyframes,
+            duration: duration,
+            playback: playback,
+            interpolation: interpolation,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    pub fn is_finished(&self, time: f64) -> bool {
+        self.playback == Playback::Once && time >= self.duration
+    }
+
+/// The pose at `time` from the start of the clip.
+    pub fn sample(&self, time: f64) -> P {
+        let time = match self.playback {
+            Playback::Loop if self.duration > 0.0 => time % self.duration,
+            _ => time.min(self.duration),
+        };
+        let i = self.keyframes.iter().rposition(
+            |k| k.time <= time).unwrap_or(0);
+        let current = &self.keyframes[i];
+        let (next, next_time) = match self.keyframes.get(i + 1) {
+            Some(k) => (k, k.time),
+            None if self.playback == Playback::Loop =>
+                (&self.keyframes[0], self.duration),
+            None => return current.pose.clone(),
+        };
+        match self.interpolation {
+            Interpolation::Step => current.pose.clone(),
+            Interpolation::Linear if next_time > current.time =>
+                current.pose.blend(&next.pose, (time - current.time) /
+                                   (next_time - current.time)),
+            Interpolation::Linear => current.pose.clone(),
+        }
+    }
+}
+
+impl Clip<SpriteFrame> {
+/// A clip showing the frames of a sprite one after another, each for
+/// `frame_time`.
+    pub fn frames(frames: &[usize], frame_time: f64, playback: Playback)
+        -> Clip<SpriteFrame> {
+            Clip::new(frames.iter().enumerate().map(|(i, &f)| Keyframe {
+                time: i as f64 * frame_time,
+                pose: SpriteFrame(f),
+            }).collect(), frames.len() as f64 * frame_time, playback,
+            Interpolation::Step)
+        }
+}
+
+/// The state machine playing the clips of an entity
+///
+/// Each state of type `S` has its clip. The entity tells the animator which
+/// state it's in with `play` and lets the time pass with `advance`; the clip
+/// of the previous state fades out for `fade` seconds.
+#[derive(Debug)]
+pub struct Animator<S, P> {
+    clips: Vec<(S, Clip<P>)>,
+    /// The states which follow the ones whose clips are played once.
+    next: Vec<(S, S)>,
+    fade: f64,
+    state: S,
+    time: f64,
+    /// The previous state, the time from the start of its clip and the time
+    /// since it was left.
+    fading: Option<(S, f64, f64)>,
+}
+
+impl<S: Copy + PartialEq, P: Pose> Animator<S, P> {
+    pub fn new(initial: S, fade: f64) -> Animator<S, P> {
+        Animator {
+            clips: vec!(),
+            next: vec!(),
+            fade: fade,
+            state: initial,
+            time: 0.0,
+            fading: None,
+        }
+    }
+
+    pub fn with_clip(mut self, state: S, clip: Clip<P>) -> Animator<S, P> {
+        self.clips.push((state, clip));
+        self
+    }
+
+/// Go to `to` once the clip of `from`, which is played once, is over.
+    pub fn with_next(mut self, from: S, to: S) -> Animator<S, P> {
+        self.next.push((from, to));
+        self
+    }
+
+    fn clip(&self, state: S) -> &Clip<P> {
+        &self.clips.iter().find(|&&(s, _)| s == state).expect(
+            "no clip for the state").1
+    }
+
+    pub fn state(&self) -> S {
+        self.state
+    }
+
+/// Switch to a state
+///
+/// Staying in the same state doesn't restart its clip unless the clip is
+/// over.
+    pub fn play(&mut self, state: S) {
+        if state == self.state {
+            if self.clip(state).is_finished(self.time) {
+                self.time = 0.0;
+            }
+            return;
+        }
+        self.fading = if self.fade > 0.0 {
+            Some((self.state, self.time, 0.0))
+        } else {
+            None
+        };
+        self.state = state;
+        self.time = 0.0;
+    }
+
+    pub fn advance(&mut self, dt: f64) {
+        self.time += dt;
+        self.fading = match self.fading {
+            Some((s, t, since)) if since + dt < self.fade =>
+                Some((s, t + dt, since + dt)),
+            _ => None,
+        };
+        if self.clip(self.state).is_finished(self.time) {
+            let state = self.state;
+            let next = self.next.iter().find(|&&(s, _)| s == state).map(
+                |&(_, to)| to);
+            if let Some(to) = next {
+                self.play(to);
+            }
+        }
+    }
+
+    pub fn pose(&self) -> P {
+        let pose = self.clip(self.state).sample(self.time);
+        match self.fading {
+            Some((s, t, since)) =>
+                self.clip(s).sample(t).blend(&pose, since / self.fade),
+            None => pose,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::Vector3;
+    use super::*;
+
+    fn at(x: f64, y: f64) -> MeshPose {
+        MeshPose(vec!(Vector3(x, y, 0.0)))
+    }
+
+    #[test]
+    fn clip_test() {
+        let walk = Clip::frames(&[0, 1, 2], 0.5, Playback::Loop);
+        let frames : Vec<usize> = [0.0, 0.4, 0.5, 1.2, 1.5, 1.9].iter().map(
+            |&t| walk.sample(t).0).collect();
+        assert_eq!(frames, vec!(0, 0, 1, 2, 0, 0));
+        assert!(!walk.is_finished(100.0));
+
+        let raise = Clip::new(vec!(
+            Keyframe { time: 0.0, pose: at(0.0, 0.0) },
+            Keyframe { time: 2.0, pose: at(0.0, 4.0) }),
+            2.0, Playback::Once, Interpolation::Linear);
+        assert_eq!(raise.sample(0.5), at(0.0, 1.0));
+        // A clip played once stays at the last keyframe
+        assert_eq!(raise.sample(3.0), at(0.0, 4.0));
+        assert!(raise.is_finished(2.0));
+    }
+
+    #[test]
+    fn animator_test() {
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        enum State { Idle, Charge }
+        let mut a = Animator::new(State::Idle, 0.5)
+            .with_clip(State::Idle, Clip::new(vec!(
+                Keyframe { time: 0.0, pose: at(0.0, 0.0) }),
+                1.0, Playback::Loop, Interpolation::Step))
+            .with_clip(State::Charge, Clip::new(vec!(
+                Keyframe { time: 0.0, pose: at(2.0, 0.0) }),
+                1.0, Playback::Once, Interpolation::Step))
+            .with_next(State::Charge, State::Idle);
+
+        a.advance(0.3);
+        a.play(State::Charge);
+        assert_eq!(a.pose(), at(0.0, 0.0));
+        // Half way through the fade
+        a.advance(0.25);
+        assert_eq!(a.pose(), at(1.0, 0.0));
+        a.advance(0.25);
+        assert_eq!(a.pose(), at(2.0, 0.0));
+        assert_eq!(a.state(), State::Charge);
+
+        // Back to idle when the charge is over
+        a.advance(0.5);
+        assert_eq!(a.state(), State::Idle);
+        a.advance(0.25);
+        assert_eq!(a.pose(), at(1.0, 0.0));
+    }
+}
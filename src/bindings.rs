@@ -0,0 +1,382 @@
+//! The keys bound to the controls of the game
+//!
+//! Keys are known by the names the window system gives them, such as `H`,
+//! `Left` or `Colon`. Every control may have several keys, and a key belongs
+//! to one control at most. The bindings are kept in
+//! `~/.config/rusty-door/keys.conf`, a line per control:
+//!
+//! ```text
+//! # Comments start with a hash
+//! up = K Up W
+//! menu = Colon
+//! ```
+//!
+//! Controls missing from the file keep their default keys.
+
+use tickable::Input;
+use direction::{DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT};
+use direction::{DIR_UP_LEFT, DIR_UP_RIGHT, DIR_DOWN_LEFT, DIR_DOWN_RIGHT};
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Control {
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+    Accept,
+    Cancel,
+    Menu,
+}
+
+pub const CONTROLS: [Control; 11] = [
+    Control::Up,
+    Control::Down,
+    Control::Left,
+    Control::Right,
+    Control::UpLeft,
+    Control::UpRight,
+    Control::DownLeft,
+    Control::DownRight,
+    Control::Accept,
+    Control::Cancel,
+    Control::Menu,
+];
+
+/// The controls which always keep a key, so that the menus can be used.
+const REQUIRED: [Control; 2] = [Control::Accept, Control::Cancel];
+
+impl Control {
+    pub fn input(&self) -> Input {
+        match *self {
+            Control::Up => Input::Direction(DIR_UP),
+            Control::Down => Input::Direction(DIR_DOWN),
+            Control::Left => Input::Direction(DIR_LEFT),
+            Control::Right => Input::Direction(DIR_RIGHT),
+            Control::UpLeft => Input::Direction(DIR_UP_LEFT),
+            Control::UpRight => Input::Direction(DIR_UP_RIGHT),
+            Control::DownLeft => Input::Direction(DIR_DOWN_LEFT),
+            Control::DownRight => Input::Direction(DIR_DOWN_RIGHT),
+            Control::Accept => Input::Accept,
+            Control::Cancel => Input::Cancel,
+            Control::Menu => Input::Menu,
+        }
+    }
+
+/// The name of the control in the configuration file.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Control::Up => "up",
+            Control::Down => "down",
+            Control::Left => "left",
+            Control::Right => "right",
+            Control::UpLeft => "up-left",
+            Control::UpRight => "up-right",
+            Control::DownLeft => "down-left",
+            Control::DownRight => "down-right",
+            Control::Accept => "accept",
+            Control::Cancel => "cancel",
+            Control::Menu => "menu",
+        }
+    }
+
+    pub fn next(&self) -> Control {
+        CONTROLS[(self.position() + 1) % CONTROLS.len()]
+    }
+
+    pub fn prev(&self) -> Control {
+        CONTROLS[(self.position() + CONTROLS.len() - 1) % CONTROLS.len()]
+    }
+
+    fn position(&self) -> usize {
+        CONTROLS.iter().position(|x| self.eq(x)).unwrap()
+    }
+}
+
+/// The names of the keys, those of `glutin::VirtualKeyCode`.
+const KEY_NAMES: [&'static str; 150] = [
+    "Key1", "Key2", "Key3", "Key4", "Key5", "Key6", "Key7", "Key8", "Key9",
+    "Key0", "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+    "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z", "Escape",
+    "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+    "F13", "F14", "F15", "Snapshot", "Scroll", "Pause", "Insert", "Home",
+    "Delete", "End", "PageDown", "PageUp", "Left", "Up", "Right", "Down",
+    "Back", "Return", "Space", "Compose", "Numlock", "Numpad0", "Numpad1",
+    "Numpad2", "Numpad3", "Numpad4", "Numpad5", "Numpad6", "Numpad7", "Numpad8",
+    "Numpad9", "AbntC1", "AbntC2", "Add", "Apostrophe", "Apps", "At", "Ax",
+    "Backslash", "Calculator", "Capital", "Colon", "Comma", "Convert",
+    "Decimal", "Divide", "Equals", "Grave", "Kana", "Kanji", "LAlt", "LBracket",
+    "LControl", "LMenu", "LShift", "LWin", "Mail", "MediaSelect", "MediaStop",
+    "Minus", "Multiply", "Mute", "MyComputer", "NavigateForward",
+    "NavigateBackward", "NextTrack", "NoConvert", "NumpadComma", "NumpadEnter",
+    "NumpadEquals", "OEM102", "Period", "PlayPause", "Power", "PrevTrack",
+    "RAlt", "RBracket", "RControl", "RMenu", "RShift", "RWin", "Semicolon",
+    "Slash", "Sleep", "Stop", "Subtract", "Sysrq", "Tab", "Underline",
+    "Unlabeled", "VolumeDown", "VolumeUp", "Wake", "WebBack", "WebFavorites",
+    "WebForward", "WebHome", "WebRefresh", "WebSearch", "WebStop", "Yen",
+];
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    /// A line of the configuration file which can't be understood.
+    Syntax {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindingsError::Io(ref e) =>
+                write!(f, "can't read key bindings: {}", e),
+            BindingsError::Syntax { line, ref message } =>
+                write!(f, "keys.conf:{}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for BindingsError {
+    fn description(&self) -> &str {
+        match *self {
+            BindingsError::Io(_) => "can't read key bindings",
+            BindingsError::Syntax { .. } => "bad key bindings",
+        }
+    }
+}
+
+impl From<io::Error> for BindingsError {
+    fn from(e: io::Error) -> BindingsError {
+        BindingsError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    /// The keys of every control, in the order of `CONTROLS`.
+    keys: Vec<Vec<String>>,
+}
+
+impl Default for Bindings {
+    /// The vim keys, the arrow keys, WASD and the numpad move; only the
+    /// numpad has keys for the diagonals.
+    fn default() -> Bindings {
+        let keys = |names: &[&str]| names.iter().map(
+            |&n| n.to_string()).collect();
+        Bindings {
+            keys: vec!(
+                keys(&["K", "Up", "W", "Numpad8"]),
+                keys(&["J", "Down", "S", "Numpad2"]),
+                keys(&["H", "Left", "A", "Numpad4"]),
+                keys(&["L", "Right", "D", "Numpad6"]),
+                keys(&["Numpad7"]),
+                keys(&["Numpad9"]),
+                keys(&["Numpad1"]),
+                keys(&["Numpad3"]),
+                keys(&["Z", "Return"]),
+                keys(&["X", "Escape"]),
+                keys(&["Colon"])),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn keys(&self, control: Control) -> &[String] {
+        &self.keys[control.position()]
+    }
+
+    pub fn control(&self, key: &str) -> Option<Control> {
+        CONTROLS.iter().cloned().find(
+            |&c| self.keys(c).iter().any(|k| k == key))
+    }
+
+/// The input the key gives, if it's bound.
+    pub fn input(&self, key: &str) -> Option<Input> {
+        self.control(key).map(|c| c.input())
+    }
+
+/// Bind a key to a control, taking it from the control it was bound to;
+/// false if that's the last key of accept or cancel, which the menus can't do
+/// without.
+    pub fn bind(&mut self, control: Control, key: &str) -> bool {
+        match self.control(key) {
+            Some(c) if c == control => return true,
+            Some(c) if REQUIRED.contains(&c) && self.keys(c).len() == 1 =>
+                return false,
+            _ => (),
+        }
+        for keys in self.keys.iter_mut() {
+            keys.retain(|k| k != key);
+        }
+        self.keys[control.position()].push(key.to_string());
+        true
+    }
+
+/// Give a control its default keys back, taking them from the other
+/// controls unless they're the last of accept or cancel.
+    pub fn reset(&mut self, control: Control) {
+        self.keys[control.position()].clear();
+        for key in Bindings::default().keys(control) {
+            self.bind(control, key);
+        }
+    }
+
+    pub fn parse(src: &str) -> Result<Bindings, BindingsError> {
+        let mut bindings = Bindings::default();
+        for (i, line) in src.lines().enumerate() {
+            let error = |message: String| Err(BindingsError::Syntax {
+                line: i + 1,
+                message: message,
+            });
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let keys = match parts.next() {
+                Some(keys) => keys,
+                None => return error(format!("expected `{} = KEYS`", name)),
+            };
+            let control = match CONTROLS.iter().find(|c| c.name() == name) {
+                Some(&c) => c,
+                None => return error(format!("unknown control {}", name)),
+            };
+            bindings.keys[control.position()].clear();
+            for key in keys.split_whitespace() {
+                if !KEY_NAMES.contains(&key) {
+                    return error(format!("unknown key {}", key));
+                }
+                if !bindings.bind(control, key) {
+                    return error(format!("{} is the only key of {}", key,
+                                         bindings.control(key).unwrap()
+                                         .name()));
+                }
+            }
+            if REQUIRED.contains(&control) &&
+                bindings.keys(control).is_empty() {
+                return error(format!("{} needs a key", name));
+            }
+        }
+        Ok(bindings)
+    }
+
+/// Where the bindings are kept: in `$XDG_CONFIG_HOME` or `~/.config`.
+    pub fn path() -> Option<PathBuf> {
+        let config = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => match env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(".config"),
+                None => return None,
+            },
+        };
+        Some(config.join("rusty-door").join("keys.conf"))
+    }
+
+/// The bindings of the configuration file; the default ones if there's no
+/// file.
+    pub fn load() -> Result<Bindings, BindingsError> {
+        let path = match Bindings::path() {
+            Some(p) => p,
+            None => return Ok(Bindings::default()),
+        };
+        let mut src = String::new();
+        match File::open(&path) {
+            Ok(mut f) => { try!(f.read_to_string(&mut src)); },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(Bindings::default()),
+            Err(e) => return Err(BindingsError::Io(e)),
+        }
+        Bindings::parse(&src)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = try!(Bindings::path().ok_or(io::Error::new(
+            io::ErrorKind::NotFound, "no home directory")));
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut f = try!(File::create(&path));
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Bindings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "# The keys of every control of rusty-door"));
+        for &c in CONTROLS.iter() {
+            try!(writeln!(f, "{} = {}", c.name(), self.keys(c).join(" ")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tickable::Input;
+
+    #[test]
+    fn bindings_test() {
+        let mut b = Bindings::parse("\
+# Arrows only
+up = Up
+down = Down   # and nothing else
+left=Left H
+").unwrap();
+        assert_eq!(b.keys(Control::Up), &["Up".to_string()]);
+        assert_eq!(b.control("H"), Some(Control::Left));
+        // The keys of the controls which aren't in the file are the default
+        assert_eq!(b.control("Z"), Some(Control::Accept));
+        assert_eq!(b.control("K"), None);
+        assert_eq!(b.input("Colon"), Some(Input::Menu));
+
+        // A key moves from the control it was bound to
+        b.bind(Control::Right, "H");
+        assert_eq!(b.control("H"), Some(Control::Right));
+        assert_eq!(b.keys(Control::Left), &["Left".to_string()]);
+        b.reset(Control::Left);
+        assert_eq!(b.control("H"), Some(Control::Left));
+
+        // Accept and cancel keep their last key
+        assert!(b.bind(Control::Menu, "Z"));
+        assert!(!b.bind(Control::Menu, "Return"));
+        assert_eq!(b.keys(Control::Accept), &["Return".to_string()]);
+        b.bind(Control::Accept, "K");
+        b.bind(Control::Menu, "Return");
+        b.reset(Control::Up);
+        assert_eq!(b.control("W"), Some(Control::Up));
+        assert_eq!(b.keys(Control::Accept), &["K".to_string()]);
+        b.reset(Control::Accept);
+
+        assert_eq!(Bindings::parse(&b.to_string()).unwrap(), b);
+        // The default keys are all known
+        for &c in CONTROLS.iter() {
+            for key in Bindings::default().keys(c) {
+                assert!(KEY_NAMES.contains(&&key[..]), "{}", key);
+            }
+        }
+        match Bindings::parse("up = K\njump = Space\n") {
+            Err(BindingsError::Syntax { line: 2, .. }) => {},
+            r => panic!("{:?}", r),
+        }
+        for src in ["cancel =\n", "cancel = Z\naccept = Z\n",
+                    "up = Up Uo\n"].iter() {
+            match Bindings::parse(src) {
+                Err(BindingsError::Syntax { .. }) => {},
+                r => panic!("{:?}", r),
+            }
+        }
+    }
+}
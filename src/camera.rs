@@ -0,0 +1,299 @@
+use geometry::{Aabb, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays diverge from the camera position; the vertical field of view is
+    /// given in radians.
+    Perspective(f64),
+    /// Rays are parallel to the view direction; the height of the visible
+    /// area is given in world units.
+    Orthographic(f64),
+}
+
+/// The point of view the world is rendered from
+///
+/// World coordinates are left-handed: looking along the Z axis with the Y axis
+/// pointing up, the X axis points to the right. World axes aren't fixed to
+/// the screen, though: the camera is looking from `position` towards
+/// `target`, and `up` tells which direction is shown as upwards on the
+/// screen. The visible area is `aspect` times wider than it is high, and only
+/// the points whose distance from the camera along the view direction is
+/// between `near` and `far` are shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vector3<f64>,
+    pub target: Vector3<f64>,
+    pub up: Vector3<f64>,
+    pub projection: Projection,
+    pub aspect: f64,
+    pub near: f64,
+    pub far: f64,
+}
+
+impl Camera {
+/// A camera looking down the Z axis at a `width` by `height` rectangle
+///
+/// The rectangle has its bottom left corner at the origin, so a point at
+/// `(x, y, z)` is shown at the pixel `(x, height - y)` of a `width` by
+/// `height` canvas.
+    pub fn top_down(width: f64, height: f64) -> Camera {
+        Camera {
+            position: Vector3(width / 2.0, height / 2.0, -1.0),
+            target: Vector3(width / 2.0, height / 2.0, 0.0),
+            up: Vector3(0.0, 1.0, 0.0),
+            projection: Projection::Orthographic(height),
+            aspect: width / height,
+            near: 0.0,
+            far: 1000.0,
+        }
+    }
+
+    pub fn with_aspect(self, aspect: f64) -> Camera {
+        Camera {
+            aspect: aspect,
+            ..self
+        }
+    }
+
+/// The unit vectors pointing forward, to the right and upwards from the
+/// camera's point of view.
+    pub fn basis(&self) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+        let forward = (self.target - self.position).normalize();
+        let right = (self.up * forward).normalize();
+        let up = forward * right;
+        (forward, right, up)
+    }
+
+/// The ray through a point of the screen
+///
+/// The screen spans from -1 to 1 on both axes, with `(-1, -1)` being the
+/// bottom left corner. Returns the origin and the unit direction of the ray.
+    pub fn ray(&self, x: f64, y: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let (forward, right, up) = self.basis();
+        match self.projection {
+            Projection::Perspective(fov) => {
+                let h = (fov / 2.0).tan();
+                (self.position, (forward + right * (x * h * self.aspect) +
+                                 up * (y * h)).normalize())
+            },
+            Projection::Orthographic(height) => {
+                let h = height / 2.0;
+                (self.position + right * (x * h * self.aspect) + up * (y * h),
+                 forward)
+            }
+        }
+    }
+
+/// The point of the screen a point of the world is shown at
+///
+/// The inverse of `ray`; `None` for the points which are behind a
+/// perspective camera and can't be shown.
+    pub fn project(&self, p: Vector3<f64>) -> Option<(f64, f64)> {
+        let (forward, right, up) = self.basis();
+        let d = p - self.position;
+        match self.projection {
+            Projection::Perspective(fov) => {
+                let depth = d.dot(forward);
+                if depth <= 0.0 {
+                    return None;
+                }
+                let h = (fov / 2.0).tan() * depth;
+                Some((d.dot(right) / (h * self.aspect), d.dot(up) / h))
+            },
+            Projection::Orthographic(height) => {
+                let h = height / 2.0;
+                Some((d.dot(right) / (h * self.aspect), d.dot(up) / h))
+            }
+        }
+    }
+
+/// The distance from the camera to a point along the view direction.
+    pub fn depth(&self, p: Vector3<f64>) -> f64 {
+        let (forward, _, _) = self.basis();
+        (p - self.position).dot(forward)
+    }
+
+/// Whether the camera sees the back of a surface at `point` whose front is
+/// in the direction of `normal`.
+    pub fn faces_away(&self, point: Vector3<f64>, normal: Vector3<f64>)
+        -> bool {
+            let view = match self.projection {
+                Projection::Perspective(_) => point - self.position,
+                Projection::Orthographic(_) => self.basis().0,
+            };
+            view.dot(normal) >= 0.0
+        }
+
+/// The part of the world the camera shows.
+    pub fn frustum(&self) -> Frustum {
+        let (forward, _, _) = self.basis();
+        // The corners of the screen at the near and the far distance
+        let corner = |x, y, depth| {
+            let (origin, dir) = self.ray(x, y);
+            origin + dir * ((depth - self.depth(origin)) / dir.dot(forward))
+        };
+        let screen = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let near : Vec<Vector3<f64>> = screen.iter().map(
+            |&(x, y)| corner(x, y, self.near)).collect();
+        let far : Vec<Vector3<f64>> = screen.iter().map(
+            |&(x, y)| corner(x, y, self.far)).collect();
+        let center = near.iter().chain(far.iter()).fold(
+            Vector3(0.0, 0.0, 0.0), |acc, &p| acc + p) * (1.0 / 8.0);
+
+        // The sides go through two far corners and a near one, as the near
+        // corners of a perspective camera may all be the same point
+        let mut planes = vec!(Plane::new(forward, near[0]),
+                              Plane::new(forward * -1.0, far[0]));
+        for i in 0 .. 4 {
+            let j = (i + 1) % 4;
+            let n = (far[i] - near[i]) * (far[j] - near[i]);
+            let plane = Plane::new(n, near[i]);
+            planes.push(if plane.distance(center) < 0.0 {
+                Plane::new(n * -1.0, near[i])
+            } else {
+                plane
+            });
+        }
+        Frustum { planes: planes }
+    }
+}
+
+/// The points `p` for which `normal.dot(p) + offset` isn't negative.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Plane {
+    normal: Vector3<f64>,
+    offset: f64,
+}
+
+impl Plane {
+    fn new(normal: Vector3<f64>, point: Vector3<f64>) -> Plane {
+        let normal = normal.normalize();
+        Plane {
+            normal: normal,
+            offset: -normal.dot(point),
+        }
+    }
+
+    fn distance(&self, p: Vector3<f64>) -> f64 {
+        self.normal.dot(p) + self.offset
+    }
+}
+
+/// The region of space seen by a camera, bounded by six planes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frustum {
+    planes: Vec<Plane>,
+}
+
+impl Frustum {
+    pub fn contains(&self, p: Vector3<f64>) -> bool {
+        self.planes.iter().all(|pl| pl.distance(p) >= 0.0)
+    }
+
+/// Whether a box may be partly inside
+///
+/// Boxes which are outside but close to a corner may be reported as
+/// intersecting, which only costs some work that could have been spared.
+    pub fn intersects(&self, b: &Aabb) -> bool {
+        self.planes.iter().all(|pl| {
+            // The corner farthest along the normal
+            let n = pl.normal;
+            let p = Vector3(if n.0 >= 0.0 { b.max.0 } else { b.min.0 },
+                            if n.1 >= 0.0 { b.max.1 } else { b.min.1 },
+                            if n.2 >= 0.0 { b.max.2 } else { b.min.2 });
+            pl.distance(p) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{Aabb, Vector3};
+    use super::{Camera, Projection};
+
+    fn close(a: Vector3<f64>, b: Vector3<f64>) -> bool {
+        let d = a - b;
+        d.dot(d) < 1e-18
+    }
+
+    #[test]
+    fn orientation_test() {
+        // The origin is at the bottom left, the X axis going right and the
+        // Y axis going up the screen
+        let c = Camera::top_down(40.0, 30.0);
+        assert_eq!(c.project(Vector3(0.0, 0.0, 5.0)), Some((-1.0, -1.0)));
+        assert_eq!(c.project(Vector3(40.0, 30.0, 5.0)), Some((1.0, 1.0)));
+        let (forward, right, up) = c.basis();
+        assert!(close(forward, Vector3(0.0, 0.0, 1.0)));
+        assert!(close(right, Vector3(1.0, 0.0, 0.0)));
+        assert!(close(up, Vector3(0.0, 1.0, 0.0)));
+        let (origin, dir) = c.ray(-1.0, -1.0);
+        assert!(close(origin, Vector3(0.0, 0.0, -1.0)));
+        assert!(close(dir, forward));
+        assert_eq!(c.depth(Vector3(7.0, 3.0, 2.0)), 3.0);
+    }
+
+    #[test]
+    fn projection_test() {
+        let perspective = Camera {
+            position: Vector3(1.0, 2.0, -3.0),
+            target: Vector3(2.0, 2.5, 1.0),
+            projection: Projection::Perspective(1.0),
+            aspect: 1.5,
+            ..Camera::top_down(40.0, 30.0)
+        };
+        let orthographic = Camera {
+            projection: Projection::Orthographic(8.0),
+            ..perspective
+        };
+        for &c in [perspective, orthographic].iter() {
+            // A point along a ray is shown where the ray goes through
+            for &(x, y) in [(0.0, 0.0), (-0.5, 0.75), (0.9, -0.3)].iter() {
+                let (origin, dir) = c.ray(x, y);
+                let (px, py) = c.project(origin + dir * 6.0).unwrap();
+                assert!((px - x).abs() < 1e-9 && (py - y).abs() < 1e-9);
+            }
+            // And a point is on the ray through where it's shown
+            let p = Vector3(3.0, -1.0, 4.0);
+            let (x, y) = c.project(p).unwrap();
+            let (origin, dir) = c.ray(x, y);
+            let t = (p - origin).dot(dir);
+            assert!(close(origin + dir * t, p));
+        }
+        // Only a perspective camera can't show what is behind it
+        assert_eq!(perspective.project(Vector3(1.0, 2.0, -5.0)), None);
+        assert!(orthographic.project(Vector3(1.0, 2.0, -5.0)).is_some());
+    }
+
+    #[test]
+    fn frustum_test() {
+        let aabb = |x: f64, y: f64, z: f64| Aabb {
+            min: Vector3(x, y, z),
+            max: Vector3(x + 1.0, y + 1.0, z + 1.0),
+        };
+        let f = Camera::top_down(40.0, 30.0).frustum();
+        assert!(f.contains(Vector3(20.0, 15.0, 2.0)));
+        assert!(f.intersects(&aabb(-0.5, -0.5, 2.0)));
+        assert!(!f.intersects(&aabb(-2.0, 10.0, 2.0)));
+        assert!(!f.intersects(&aabb(10.0, 30.5, 2.0)));
+        assert!(!f.intersects(&aabb(10.0, 10.0, -3.0)));
+
+        let eye = Camera {
+            position: Vector3(0.0, 0.0, 0.0),
+            target: Vector3(0.0, 0.0, 1.0),
+            projection: Projection::Perspective(::std::f64::consts::PI / 2.0),
+            aspect: 1.0,
+            ..Camera::top_down(40.0, 30.0)
+        };
+        let f = eye.frustum();
+        // The view widens with the distance
+        assert!(!f.intersects(&aabb(3.0, 0.0, 1.0)));
+        assert!(f.intersects(&aabb(3.0, 0.0, 4.0)));
+        assert!(!f.intersects(&aabb(0.0, 0.0, -5.0)));
+
+        assert!(eye.faces_away(Vector3(0.0, 0.0, 5.0),
+                               Vector3(0.0, 0.0, 1.0)));
+        assert!(!eye.faces_away(Vector3(0.0, 0.0, 5.0),
+                                Vector3(0.0, 0.0, -1.0)));
+    }
+}
@@ -74,6 +74,11 @@ pub const DIR_DOWN:  Direction = Direction(LeftRight::Middle, UpDown::Down);
 pub const DIR_LEFT:  Direction = Direction(LeftRight::Left,   UpDown::Middle);
 pub const DIR_RIGHT: Direction = Direction(LeftRight::Right,  UpDown::Middle);
 
+pub const DIR_UP_LEFT:    Direction = Direction(LeftRight::Left,  UpDown::Up);
+pub const DIR_UP_RIGHT:   Direction = Direction(LeftRight::Right, UpDown::Up);
+pub const DIR_DOWN_LEFT:  Direction = Direction(LeftRight::Left,  UpDown::Down);
+pub const DIR_DOWN_RIGHT: Direction = Direction(LeftRight::Right, UpDown::Down);
+
 impl Direction {
 
 /// Multiply a direction by a complex number
@@ -134,6 +139,51 @@ impl Direction {
     pub fn rot_ctr_cw(&self) -> Direction {
         self.rot_by(1, 1)
     }
+
+/// Whether a direction is between two of the axes
+    pub fn is_diagonal(&self) -> bool {
+        self.0 != LeftRight::Middle && self.1 != UpDown::Middle
+    }
+
+/// The part of a direction along the X axis, and the one along the Y axis
+///
+/// # Examples
+///
+/// ```
+/// use direction;
+/// assert_eq!(direction::DIR_UP_LEFT.split(),
+///            (direction::DIR_LEFT, direction::DIR_UP));
+/// ```
+    pub fn split(&self) -> (Direction, Direction) {
+        (Direction(self.0, UpDown::Middle),
+         Direction(LeftRight::Middle, self.1))
+    }
+
+/// Combine two directions, as when two keys are held together
+///
+/// Each part of the direction is taken from `self`, unless it's neutral
+/// there, so that combining two opposite directions gives `self`.
+///
+/// # Examples
+///
+/// ```
+/// use direction;
+/// let up = direction::DIR_UP;
+/// assert_eq!(up.and(direction::DIR_RIGHT), direction::DIR_UP_RIGHT);
+/// assert_eq!(up.and(direction::DIR_DOWN), up);
+/// ```
+    pub fn and(&self, other: Direction) -> Direction {
+        Direction(if self.0 == LeftRight::Middle { other.0 } else { self.0 },
+                  if self.1 == UpDown::Middle { other.1 } else { self.1 })
+    }
+
+/// The angle of a direction counterclockwise from the right, in radians
+///
+/// The angle is from -pi to pi; a neutral direction has the angle 0.
+    pub fn angle(&self) -> f64 {
+        let Direction(x, y) = *self;
+        (y as i8 as f64).atan2(x as i8 as f64)
+    }
 }
 
 #[cfg(test)]
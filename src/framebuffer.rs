@@ -0,0 +1,243 @@
+use geometry::RGB;
+use std::ops::{Index, IndexMut};
+
+/// A rectangle of values, such as the colours or the depths of the pixels
+///
+/// The values are stored row after row in a single vector, the first row
+/// being the top one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameBuffer<T> {
+    width: usize,
+    height: usize,
+    data: Vec<T>,
+}
+
+/// The order the rows are written out in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowOrder {
+    TopDown,
+    /// The order OpenGL textures use.
+    BottomUp,
+}
+
+/// A rectangular part of a `FrameBuffer`.
+#[derive(Clone, Copy, Debug)]
+pub struct View<'a, T: 'a> {
+    buffer: &'a FrameBuffer<T>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<T: Copy> FrameBuffer<T> {
+    pub fn new(width: usize, height: usize, value: T) -> FrameBuffer<T> {
+        FrameBuffer {
+            width: width,
+            height: height,
+            data: vec!(value; width * height),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+/// All the values, row after row.
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn row(&self, y: usize) -> &[T] {
+        &self.data[y * self.width .. (y + 1) * self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [T] {
+        &mut self.data[y * self.width .. (y + 1) * self.width]
+    }
+
+    pub fn rows<'a>(&'a self) -> Box<Iterator<Item=&'a [T]> + 'a> {
+        Box::new((0 .. self.height).map(move |y| self.row(y)))
+    }
+
+    pub fn fill(&mut self, value: T) {
+        for x in self.data.iter_mut() {
+            *x = value;
+        }
+    }
+
+/// The part of the buffer with its top left corner at `(x, y)`, cut down to
+/// what lies inside the buffer.
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize)
+        -> View<T> {
+            let x = x.min(self.width);
+            let y = y.min(self.height);
+            View {
+                buffer: self,
+                x: x,
+                y: y,
+                width: width.min(self.width - x),
+                height: height.min(self.height - y),
+            }
+        }
+
+    pub fn whole(&self) -> View<T> {
+        self.view(0, 0, self.width, self.height)
+    }
+
+/// Copy a view into the buffer, with its top left corner at `(x, y)`
+///
+/// The parts which don't fit into the buffer are left out.
+    pub fn blit(&mut self, src: &View<T>, x: isize, y: isize) {
+        for sy in 0 .. src.height {
+            let dy = y + sy as isize;
+            if dy < 0 || dy >= self.height as isize {
+                continue;
+            }
+            let row = src.row(sy);
+            // The columns of the source which land inside the buffer
+            let start = (-x).max(0) as usize;
+            let end = (self.width as isize - x).max(0).min(
+                src.width as isize) as usize;
+            if start >= end {
+                continue;
+            }
+            let dx = (x + start as isize) as usize;
+            self.row_mut(dy as usize)[dx .. dx + end - start].copy_from_slice(
+                &row[start .. end]);
+        }
+    }
+}
+
+impl<T> Index<(usize, usize)> for FrameBuffer<T> {
+    type Output = T;
+
+    fn index(&self, (x, y): (usize, usize)) -> &T {
+        assert!(x < self.width && y < self.height);
+        &self.data[y * self.width + x]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for FrameBuffer<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut T {
+        assert!(x < self.width && y < self.height);
+        &mut self.data[y * self.width + x]
+    }
+}
+
+impl<'a, T: Copy> View<'a, T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn row(&self, y: usize) -> &'a [T] {
+        assert!(y < self.height);
+        &self.buffer.row(self.y + y)[self.x .. self.x + self.width]
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> T {
+        self.row(y)[x]
+    }
+
+    pub fn to_buffer(&self) -> FrameBuffer<T> {
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for y in 0 .. self.height {
+            data.extend_from_slice(self.row(y));
+        }
+        FrameBuffer {
+            width: self.width,
+            height: self.height,
+            data: data,
+        }
+    }
+}
+
+impl FrameBuffer<RGB> {
+/// Append the colours to `out` as three bytes per pixel.
+    pub fn write_rgb8(&self, out: &mut Vec<u8>, order: RowOrder) {
+        out.reserve(self.data.len() * 3);
+        self.each_row(order, |row| for c in row {
+            out.extend_from_slice(&[c.0, c.1, c.2]);
+        });
+    }
+
+/// Append the colours to `out` as four bytes per pixel, all of them opaque.
+    pub fn write_rgba8(&self, out: &mut Vec<u8>, order: RowOrder) {
+        out.reserve(self.data.len() * 4);
+        self.each_row(order, |row| for c in row {
+            out.extend_from_slice(&[c.0, c.1, c.2, 0xff]);
+        });
+    }
+
+    fn each_row<F: FnMut(&[RGB])>(&self, order: RowOrder, mut f: F) {
+        match order {
+            RowOrder::TopDown => for y in 0 .. self.height {
+                f(self.row(y))
+            },
+            RowOrder::BottomUp => for y in (0 .. self.height).rev() {
+                f(self.row(y))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::RGB;
+    use super::{FrameBuffer, RowOrder};
+
+    #[test]
+    fn framebuffer_test() {
+        let mut fb = FrameBuffer::new(4, 3, 0);
+        for y in 0 .. 3 {
+            for x in 0 .. 4 {
+                fb[(x, y)] = y * 10 + x;
+            }
+        }
+        assert_eq!(fb.row(1), &[10, 11, 12, 13]);
+        assert_eq!(fb.rows().count(), 3);
+
+        // Views are cut down to the buffer
+        let v = fb.view(2, 1, 5, 5);
+        assert_eq!((v.width(), v.height()), (2, 2));
+        assert_eq!(v.row(1), &[22, 23]);
+        assert_eq!(v.get(0, 0), 12);
+
+        // Blitting leaves out what doesn't fit
+        let src = v.to_buffer();
+        let mut dst = FrameBuffer::new(3, 3, 0);
+        dst.blit(&src.whole(), -1, 2);
+        assert_eq!(dst.data(), &[0, 0, 0, 0, 0, 0, 13, 0, 0]);
+        dst.blit(&src.whole(), 2, 0);
+        assert_eq!(dst.data(), &[0, 0, 12, 0, 0, 22, 13, 0, 0]);
+    }
+
+    #[test]
+    fn bytes_test() {
+        let mut fb = FrameBuffer::new(1, 2, RGB(1, 2, 3));
+        fb[(0, 1)] = RGB(4, 5, 6);
+        let mut out = vec!();
+        fb.write_rgb8(&mut out, RowOrder::TopDown);
+        assert_eq!(out, vec!(1, 2, 3, 4, 5, 6));
+        out.clear();
+        fb.write_rgba8(&mut out, RowOrder::BottomUp);
+        assert_eq!(out, vec!(4, 5, 6, 255, 1, 2, 3, 255));
+
+        // Four bytes a pixel, appended to what's there, every one opaque
+        let mut fb = FrameBuffer::new(2, 2, RGB(0, 0, 0));
+        fb[(1, 0)] = RGB(7, 8, 9);
+        let mut out = vec!(42);
+        fb.write_rgba8(&mut out, RowOrder::TopDown);
+        assert_eq!(out.len(), 1 + 2 * 2 * 4);
+        assert_eq!(&out[1 .. 9], &[0, 0, 0, 255, 7, 8, 9, 255]);
+        assert!(out[1 ..].chunks(4).all(|p| p[3] == 0xff));
+    }
+}
@@ -1,13 +1,60 @@
 use tickable::{Tickable,Input};
 use labyrinth;
+use direction::{Direction, DIR_RIGHT};
+use std::collections::VecDeque;
+use std::f64::consts::PI;
 use std::time;
 use std::fmt;
 
+/// How many cells the player walks in a second.
+pub const SPEED: f64 = 4.0;
+
+/// How fast the player turns around, in radians per second.
+pub const TURN_SPEED: f64 = 4.0 * PI;
+
+/// The share of a step within which a second direction turns it into a
+/// diagonal one, for the keys pressed together.
+const CHORD: f64 = 0.25;
+
+/// A move of the player from a cell to its neighbour.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Step {
+    pub from : labyrinth::Point,
+    pub to : labyrinth::Point,
+    /// The share of the way which is behind, from 0 to 1.
+    pub progress : f64,
+}
+
+impl Step {
+    /// How many cells long the step is: 1, or more for diagonal ones.
+    pub fn length(&self) -> f64 {
+        let dx = self.to.x as f64 - self.from.x as f64;
+        let dy = self.to.y as f64 - self.from.y as f64;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
 pub struct Game {
     pub field : labyrinth::Labyrinth,
+    pub movement : labyrinth::Movement,
+    pub start : labyrinth::Point,
+    /// The cell the player is in, or is leaving while moving.
     pub player : labyrinth::Point,
+    pub step : Option<Step>,
+    /// The direction the player is to face.
+    pub heading : Direction,
+    /// The angle the face of the player is at, turning towards the heading;
+    /// see `Direction::angle`.
+    pub facing : f64,
+    /// The direction to go to once the step is over.
+    pub queued : Option<Direction>,
+    /// The direction of the key held down, to keep going that way.
+    pub held : Option<Direction>,
+    /// The steps left on the way to a cell the player was sent to.
+    pub route : VecDeque<Direction>,
     pub playtime : time::Duration,
     pub walked : labyrinth::Field,
+    pub torches : Vec<labyrinth::Torch>,
 }
 
 impl fmt::Debug for Game {
@@ -30,12 +77,167 @@ impl fmt::Debug for Game {
 }
 
 impl Game {
-    pub fn new(width : usize, height : usize, seed: u16) -> Game {
+    pub fn new(width : usize, height : usize, seed: u16,
+               torch_spacing : usize, movement : labyrinth::Movement)
+        -> Game {
+        let field = labyrinth::Labyrinth::new(width, height, seed, movement);
+        let torches = field.torches(torch_spacing);
+        let start = labyrinth::Point { x : 1, y : 1 };
+        let mut walked = labyrinth::Field::new(width, height, false);
+        walked[start] = true;
         Game {
-            field : labyrinth::Labyrinth::new(width, height, seed),
-            player : labyrinth::Point { x : 1, y : 1 },
+            field : field,
+            movement : movement,
+            start : start,
+            player : start,
+            step : None,
+            heading : DIR_RIGHT,
+            facing : DIR_RIGHT.angle(),
+            queued : None,
+            held : None,
+            route : VecDeque::new(),
             playtime : time::Duration::new(0, 0),
-            walked : labyrinth::Field::new(width, height, false),
+            walked : walked,
+            torches : torches,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.step.is_some()
+    }
+
+    /// Where the player is: the row and the column of the cell, or a point
+    /// between two cells while moving.
+    pub fn position(&self) -> (f64, f64) {
+        match self.step {
+            Some(s) => {
+                let t = s.progress;
+                (s.from.x as f64 + (s.to.x as f64 - s.from.x as f64) * t,
+                 s.from.y as f64 + (s.to.y as f64 - s.from.y as f64) * t)
+            },
+            None => (self.player.x as f64, self.player.y as f64),
+        }
+    }
+
+    /// Head in a direction, and step there unless there's a wall; the
+    /// directions the player can't move in are left alone.
+    fn start(&mut self, d: Direction) {
+        if !self.movement.allows(d) {
+            return;
+        }
+        self.heading = d;
+        if self.can_start(d) {
+            self.step = Some(Step { from : self.player,
+                                    to : self.player.neighbor(d),
+                                    progress : 0.0 });
+        }
+    }
+
+    /// Whether a step from the cell of the player can be taken that way.
+    fn can_start(&self, d: Direction) -> bool {
+        self.movement.allows(d) &&
+            self.field.can_step(self.player, d, self.movement)
+    }
+
+    /// Turn a step which has just begun into a diagonal one, adding a
+    /// direction to its own; false if it's too late or there's no way.
+    fn chord(&mut self, d: Direction) -> bool {
+        let s = match self.step {
+            Some(s) if s.progress < CHORD => s,
+            _ => return false,
+        };
+        let c = self.heading.and(d);
+        if !c.is_diagonal() || c == self.heading ||
+            !self.field.can_step(s.from, c, self.movement) {
+                return false;
+            }
+        self.heading = c;
+        self.step = Some(Step { to : s.from.neighbor(c), ..s });
+        true
+    }
+
+    /// The direction of the next step: the queued one, or else the next one
+    /// on the route, or else the held one.
+    fn next(&mut self) -> Option<Direction> {
+        if let Some(d) = self.queued.take() {
+            return Some(d);
+        }
+        if let Some(d) = self.route.pop_front() {
+            // Each step of the route counts as a move of its own
+            self.playtime += time::Duration::new(1, 0);
+            return Some(d);
+        }
+        // So does each step in the held direction, once it can be taken
+        if let Some(d) = self.held {
+            if self.can_start(d) {
+                self.playtime += time::Duration::new(1, 0);
+            }
+        }
+        self.held
+    }
+
+    /// Send the player to a cell along the shortest way, step by step;
+    /// false if there's no way there.
+    ///
+    /// A step which is under way is finished first.
+    pub fn walk_to(&mut self, target: labyrinth::Point) -> bool {
+        let from = self.step.map_or(self.player, |s| s.to);
+        if self.field.0.get(target) != Some(&false) {
+            return false;
+        }
+        match self.search(from, target, |_| true) {
+            Some(steps) => {
+                self.route = steps.into_iter().map(|(_, d)| d).collect();
+                self.queued = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Let `dt` seconds pass, moving the player and turning it to its
+    /// heading
+    ///
+    /// A step which is over is followed by the queued one, or else by the
+    /// next one on the route or in the held direction, without stopping in
+    /// between.
+    pub fn advance(&mut self, dt: f64) {
+        if self.step.is_none() && !self.is_finished() {
+            if let Some(d) = self.next() {
+                self.start(d);
+            }
+        }
+
+        let mut diff = (self.heading.angle() - self.facing) % (2.0 * PI);
+        if diff > PI {
+            diff -= 2.0 * PI;
+        } else if diff < -PI {
+            diff += 2.0 * PI;
+        }
+        if diff.abs() <= TURN_SPEED * dt {
+            self.facing = self.heading.angle();
+        } else {
+            self.facing += TURN_SPEED * dt * diff.signum();
+        }
+
+        let mut left = dt;
+        while let Some(mut s) = self.step {
+            s.progress += left * SPEED / s.length();
+            if s.progress < 1.0 {
+                self.step = Some(s);
+                break;
+            }
+            // The time which is left after the step
+            left = (s.progress - 1.0) * s.length() / SPEED;
+            self.step = None;
+            self.walked[s.from] = !self.walked[s.to];
+            self.walked[s.to] = true;
+            self.player = s.to;
+            if !self.is_finished() {
+                if let Some(d) = self.next() {
+                    self.start(d);
+                }
+            }
         }
     }
 
@@ -45,21 +247,75 @@ impl Game {
             y : self.field.0.width()  - 1
         })
     }
+
+    /// The cells of the thread, from the start to the player
+    ///
+    /// The thread goes through the cells which have been walked on an odd
+    /// number of times, taking the shortest way if there are several.
+    pub fn thread(&self) -> Vec<labyrinth::Point> {
+        let on_thread = |p: labyrinth::Point|
+            p == self.player || self.walked.get(p) == Some(&true);
+        if !on_thread(self.start) {
+            return vec!(self.player);
+        }
+        match self.search(self.start, self.player, on_thread) {
+            Some(steps) => Some(self.start).into_iter().chain(
+                steps.into_iter().map(|(p, _)| p)).collect(),
+            None => vec!(self.player),
+        }
+    }
+
+    /// The shortest way from a cell to another through the cells `open` lets
+    /// through, as the cells it steps on and the directions of the steps
+    fn search<F>(&self, from: labyrinth::Point, to: labyrinth::Point,
+                 open: F) -> Option<Vec<(labyrinth::Point, Direction)>>
+        where F: Fn(labyrinth::Point) -> bool {
+            // The cell each cell was reached from, and in which direction
+            let mut reached = labyrinth::Field::new(
+                self.field.0.width(), self.field.0.height(), None);
+            let mut queue = VecDeque::new();
+            queue.push_back(from);
+            while let Some(p) = queue.pop_front() {
+                if p == to {
+                    let mut steps = vec!();
+                    let mut p = p;
+                    while p != from {
+                        let (q, d) = reached[p].unwrap();
+                        steps.push((p, d));
+                        p = q;
+                    }
+                    steps.reverse();
+                    return Some(steps);
+                }
+                for &d in self.movement.directions() {
+                    let q = p.neighbor(d);
+                    if q != from && reached.get(q) == Some(&None) &&
+                        open(q) && self.field.can_step(p, d, self.movement) {
+                        reached[q] = Some((p, d));
+                        queue.push_back(q);
+                    }
+                }
+            }
+            None
+        }
 }
 
 impl Tickable for Game {
 
     fn tick(&mut self, input: Option<Input>) -> Option<Box<Tickable>> {
-        self.walked[self.player] = true;
         self.playtime += time::Duration::new(1, 0);
+        // Any input stops the walk to a cell
+        if input.is_some() {
+            self.route.clear();
+        }
         match input {
-            Some(Input::Direction(d)) => {
-                let p = self.player.neighbor(d);
-                if let Some(&false) = self.field.0.get(p) {
-                    self.walked[self.player] = !self.walked[p];
-                    self.player = p;
-                }
-            },
+            // The next step is taken once the current one is over, unless
+            // the two make a diagonal one
+            Some(Input::Direction(d)) if self.is_moving() =>
+                if !self.chord(d) {
+                    self.queued = Some(d);
+                },
+            Some(Input::Direction(d)) => self.start(d),
             _ => (),
         }
         None
@@ -67,3 +323,173 @@ impl Tickable for Game {
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tickable::{Tickable, Input};
+    use direction::{Direction, DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT,
+                    DIR_UP_RIGHT};
+    use labyrinth::{Field, Movement, Point};
+    use std::time;
+
+    /// Make the moves one after another, each to its end.
+    fn walk(game: &mut Game, moves: &[Direction]) {
+        for &d in moves.iter() {
+            game.tick(Some(Input::Direction(d)));
+            game.advance(1.0);
+        }
+    }
+
+    #[test]
+    fn thread_test() {
+        let mut game = Game::new(7, 5, 1, 4, Movement::FourWay);
+        // A corridor around a wall
+        game.field.0 = Field::new(7, 5, true);
+        let corridor = [(1, 1), (1, 2), (1, 3), (2, 3), (3, 3), (3, 2),
+                        (3, 1)];
+        let cells : Vec<Point> = corridor.iter().map(
+            |&(x, y)| Point { x: x, y: y }).collect();
+        for &p in cells.iter() {
+            game.field.0[p] = false;
+        }
+        assert_eq!(game.thread(), vec!(cells[0]));
+
+        walk(&mut game, &[DIR_RIGHT, DIR_RIGHT, DIR_UP, DIR_UP, DIR_LEFT,
+                          DIR_LEFT]);
+        assert_eq!(game.thread(), cells);
+
+        // Going back the same way winds the thread up
+        walk(&mut game, &[DIR_RIGHT, DIR_RIGHT, DIR_DOWN]);
+        assert_eq!(game.thread(), cells[.. 4].to_vec());
+    }
+
+    #[test]
+    fn held_test() {
+        let mut game = Game::new(7, 5, 1, 4, Movement::FourWay);
+        game.field.0 = Field::new(7, 5, true);
+        for y in 1 .. 5 {
+            game.field.0[Point { x: 1, y: y }] = false;
+        }
+        // The press is a move, and so is every step the held key adds
+        game.tick(Some(Input::Direction(DIR_RIGHT)));
+        game.held = Some(DIR_RIGHT);
+        game.advance(2.5 / SPEED);
+        assert_eq!(game.playtime, time::Duration::new(3, 0));
+        game.advance(1.0);
+        assert_eq!(game.player, Point { x: 1, y: 4 });
+        assert_eq!(game.playtime, time::Duration::new(3, 0));
+
+        // Pushing against the wall isn't
+        for _ in 0 .. 10 {
+            game.advance(0.1);
+        }
+        assert!(!game.is_moving());
+        assert_eq!(game.playtime, time::Duration::new(3, 0));
+    }
+
+    #[test]
+    fn walk_to_test() {
+        let mut game = Game::new(7, 5, 1, 4, Movement::FourWay);
+        game.field.0 = Field::new(7, 5, true);
+        let corridor = [(1, 1), (1, 2), (1, 3), (2, 3), (3, 3), (3, 2),
+                        (3, 1)];
+        for &(x, y) in corridor.iter() {
+            game.field.0[Point { x: x, y: y }] = false;
+        }
+        assert!(!game.walk_to(Point { x: 2, y: 1 }));
+
+        // The way goes around the wall, a step after another
+        assert!(game.walk_to(Point { x: 3, y: 1 }));
+        assert_eq!(game.route.len(), 6);
+        game.advance(2.5 / SPEED);
+        assert_eq!(game.player, Point { x: 1, y: 3 });
+        assert_eq!(game.playtime, time::Duration::new(3, 0));
+
+        // Any input stops the player once the step is over
+        game.tick(Some(Input::Cancel));
+        game.advance(1.0);
+        assert!(!game.is_moving());
+        assert_eq!(game.player, Point { x: 2, y: 3 });
+    }
+
+    #[test]
+    fn diagonal_test() {
+        // A bend, whose corner may be cut
+        let bend = |movement| {
+            let mut game = Game::new(7, 5, 1, 4, movement);
+            game.field.0 = Field::new(7, 5, true);
+            for &(x, y) in [(1, 1), (1, 2), (2, 2)].iter() {
+                game.field.0[Point { x: x, y: y }] = false;
+            }
+            game
+        };
+        let mut game = bend(Movement::FourWay);
+        game.tick(Some(Input::Direction(DIR_UP_RIGHT)));
+        assert!(!game.is_moving());
+        assert_eq!(game.heading, DIR_RIGHT);
+        let mut game = bend(Movement::EightWay { cut_corners: false });
+        assert!(game.walk_to(Point { x: 2, y: 2 }));
+        assert_eq!(game.route.len(), 2);
+
+        // A diagonal step is longer
+        let mut game = bend(Movement::EightWay { cut_corners: true });
+        assert!(game.walk_to(Point { x: 2, y: 2 }));
+        assert_eq!(game.route, vec!(DIR_UP_RIGHT));
+        game.advance(1.0 / SPEED);
+        assert!(game.is_moving());
+        game.advance(0.5 / SPEED);
+        assert_eq!(game.player, Point { x: 2, y: 2 });
+        assert!(!game.is_moving());
+
+        // Two directions pressed one right after the other
+        let mut game = Game::new(7, 5, 1, 4,
+                                 Movement::EightWay { cut_corners: false });
+        game.field.0 = Field::new(7, 5, false);
+        game.tick(Some(Input::Direction(DIR_UP)));
+        game.advance(0.1 / SPEED);
+        game.tick(Some(Input::Direction(DIR_RIGHT)));
+        game.advance(2.0 / SPEED);
+        assert_eq!(game.player, Point { x: 2, y: 2 });
+        assert_eq!(game.heading, DIR_UP_RIGHT);
+    }
+
+    #[test]
+    fn movement_test() {
+        let mut game = Game::new(7, 5, 1, 4, Movement::FourWay);
+        game.field.0 = Field::new(7, 5, false);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        // A quarter of a step, turning to face upwards on the way
+        game.tick(Some(Input::Direction(DIR_UP)));
+        game.advance(0.25 / SPEED);
+        assert!(game.is_moving());
+        assert_eq!(game.player, Point { x: 1, y: 1 });
+        let (x, y) = game.position();
+        assert!(close(x, 1.25) && close(y, 1.0));
+        assert!(game.facing > 0.0 && game.facing < DIR_UP.angle());
+
+        // The next move waits for the step to be over, and follows it at
+        // once
+        game.tick(Some(Input::Direction(DIR_RIGHT)));
+        game.advance(1.0 / SPEED);
+        assert_eq!(game.player, Point { x: 2, y: 1 });
+        assert_eq!(game.step, Some(Step {
+            from: Point { x: 2, y: 1 },
+            to: Point { x: 2, y: 2 },
+            progress: 0.25,
+        }));
+
+        // Then the player stops
+        game.advance(1.0);
+        assert!(!game.is_moving());
+        assert_eq!(game.position(), (2.0, 2.0));
+        assert_eq!(game.facing, DIR_RIGHT.angle());
+
+        // Holding a key keeps the player going until it's released
+        game.held = Some(DIR_RIGHT);
+        game.advance(2.5 / SPEED);
+        game.held = None;
+        game.advance(1.0);
+        assert_eq!(game.player, Point { x: 2, y: 5 });
+    }
+}
@@ -1,9 +1,98 @@
 use std::ops::{Index,IndexMut,Mul,Sub,Add};
 use std::convert::From;
+use std::rc::Rc;
+use texture::{Texture, Sampling};
+use camera::Camera;
+use postprocess::Effect;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct RGB (pub u8, pub u8, pub u8);
 
+/// A colour with floating-point channels.
+///
+/// Channels are measured in the same units as in `RGB` but aren't limited to
+/// `0 ..= 255`, so that colours can be summed and scaled without losing
+/// precision or overflowing. `to_rgb` clamps the result.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RGBf (pub f64, pub f64, pub f64);
+
+impl RGB {
+    /// The colour in linear light, see `RGBf::to_linear`.
+    pub fn to_linear(&self) -> RGBf {
+        RGBf::from(*self).to_linear()
+    }
+}
+
+impl RGBf {
+    /// Decode the channels of an sRGB colour into linear light
+    ///
+    /// The brightness of sRGB channels isn't proportional to their values, so
+    /// colours can only be added and averaged after decoding. Channels of
+    /// the result go from 0 to 1.
+    pub fn to_linear(&self) -> RGBf {
+        let decode = |c: f64| {
+            let c = c / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        RGBf(decode(self.0), decode(self.1), decode(self.2))
+    }
+
+    /// Encode a colour in linear light into sRGB, see `to_linear`.
+    pub fn to_srgb(&self) -> RGB {
+        let encode = |c: f64| {
+            let c = if c > 1.0 { 1.0 } else if c > 0.0 { c } else { 0.0 };
+            255.0 * if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        };
+        RGBf(encode(self.0), encode(self.1), encode(self.2)).to_rgb()
+    }
+
+    pub fn to_rgb(&self) -> RGB {
+        let clamp = |c: f64| if c > 255.0 {
+            255
+        } else if c > 0.0 {
+            c.round() as u8
+        } else {
+            0
+        };
+        RGB(clamp(self.0), clamp(self.1), clamp(self.2))
+    }
+}
+
+impl From<RGB> for RGBf {
+    fn from(c: RGB) -> RGBf {
+        RGBf(c.0 as f64, c.1 as f64, c.2 as f64)
+    }
+}
+
+impl Add<RGBf> for RGBf {
+    type Output = RGBf;
+    fn add(self, rhs: RGBf) -> RGBf {
+        RGBf(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+impl Mul<f64> for RGBf {
+    type Output = RGBf;
+    fn mul(self, rhs: f64) -> RGBf {
+        RGBf(self.0 * rhs, self.1 * rhs, self.2 * rhs)
+    }
+}
+
+impl Mul<RGBf> for RGBf {
+    type Output = RGBf;
+    fn mul(self, rhs: RGBf) -> RGBf {
+        RGBf(self.0 * rhs.0, self.1 * rhs.1, self.2 * rhs.2)
+    }
+}
+
 #[derive(Copy,Clone)]
 pub enum Dimension {
     X,
@@ -14,7 +103,31 @@ pub enum Dimension {
 #[derive(Copy,Clone)]
 pub struct Axis (pub Dimension);
 
-#[derive(Copy,Clone,Debug)]
+#[derive(Copy,Clone,Debug,Default,PartialEq)]
+pub struct Vector2<T> (pub T, pub T);
+
+impl<T: Add<T, Output=T>> Add<Vector2<T>> for Vector2<T> {
+    type Output = Vector2<T>;
+    fn add(self, rhs: Vector2<T>) -> Vector2<T> {
+        Vector2(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
+impl<T: Sub<T, Output=T>> Sub<Vector2<T>> for Vector2<T> {
+    type Output = Vector2<T>;
+    fn sub(self, rhs: Vector2<T>) -> Vector2<T> {
+        Vector2(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
+
+impl<T: Copy + Mul<T, Output=T>> Mul<T> for Vector2<T> {
+    type Output = Vector2<T>;
+    fn mul(self, rhs: T) -> Vector2<T> {
+        Vector2(self.0 * rhs, self.1 * rhs)
+    }
+}
+
+#[derive(Copy,Clone,Debug,Default,PartialEq)]
 pub struct Vector3<T> (pub T, pub T, pub T);
 
 impl<T: Copy + Mul<T, Output=T> + Sub<T, Output=T>>
@@ -84,8 +197,258 @@ impl<T: Mul<T, Output=T> + Add<T, Output=T> + Copy> Vector3<T> {
         self.0 * rhs.0 + self.1 * rhs.1 + self.2 * rhs.2
     }
 
-    pub fn length(&self) -> T {
-        self.0 * self.0 + self.1 * self.1 + self.2 * self.2
+    pub fn length_squared(&self) -> T {
+        self.dot(*self)
+    }
+}
+
+impl Vector3<f64> {
+    pub fn length(&self) -> f64 {
+        self.length_squared().sqrt()
+    }
+
+/// The unit vector pointing the same way; the zero vector stays as it is.
+    pub fn normalize(&self) -> Vector3<f64> {
+        let len = self.length();
+        if len > 0.0 { *self * (1.0 / len) } else { *self }
+    }
+}
+
+/// A 4x4 matrix of an affine transformation
+///
+/// Points are column vectors multiplied from the right, so `a * b` applies
+/// `b` first and `a` after it. The rows are stored one after another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4(pub [[f64; 4]; 4]);
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4::scaling(Vector3(1.0, 1.0, 1.0))
+    }
+
+    pub fn translation(v: Vector3<f64>) -> Matrix4 {
+        Matrix4([[1.0, 0.0, 0.0, v.0],
+                 [0.0, 1.0, 0.0, v.1],
+                 [0.0, 0.0, 1.0, v.2],
+                 [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn scaling(v: Vector3<f64>) -> Matrix4 {
+        Matrix4([[v.0, 0.0, 0.0, 0.0],
+                 [0.0, v.1, 0.0, 0.0],
+                 [0.0, 0.0, v.2, 0.0],
+                 [0.0, 0.0, 0.0, 1.0]])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0 .. 4 {
+            for j in 0 .. 4 {
+                m[i][j] = self.0[j][i];
+            }
+        }
+        Matrix4(m)
+    }
+
+/// The matrix undoing this one, unless it squashes space into a plane.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        // Gauss-Jordan elimination with partial pivoting
+        let mut a = self.0;
+        let mut inv = Matrix4::identity().0;
+        for col in 0 .. 4 {
+            let pivot = (col .. 4).fold(col, |best, r|
+                if a[r][col].abs() > a[best][col].abs() { r } else { best });
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let k = 1.0 / a[col][col];
+            for j in 0 .. 4 {
+                a[col][j] *= k;
+                inv[col][j] *= k;
+            }
+            for r in 0 .. 4 {
+                if r != col {
+                    let f = a[r][col];
+                    for j in 0 .. 4 {
+                        a[r][j] -= f * a[col][j];
+                        inv[r][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4(inv))
+    }
+
+    pub fn transform_point(&self, p: Vector3<f64>) -> Vector3<f64> {
+        let m = &self.0;
+        let w = m[3][0] * p.0 + m[3][1] * p.1 + m[3][2] * p.2 + m[3][3];
+        self.transform_vector(p) * (1.0 / w) +
+            Vector3(m[0][3], m[1][3], m[2][3]) * (1.0 / w)
+    }
+
+/// Transform a direction, which isn't affected by translation.
+    pub fn transform_vector(&self, v: Vector3<f64>) -> Vector3<f64> {
+        let m = &self.0;
+        Vector3(m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+                m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+                m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2)
+    }
+
+/// Transform a surface normal, keeping it perpendicular to the surface even
+/// when the scaling isn't uniform.
+    pub fn transform_normal(&self, n: Vector3<f64>) -> Vector3<f64> {
+        self.inverse().map_or(n, |m| m.transpose().transform_vector(n)).
+            normalize()
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0 .. 4 {
+            for j in 0 .. 4 {
+                m[i][j] = (0 .. 4).map(|k| self.0[i][k] * rhs.0[k][j]).sum();
+            }
+        }
+        Matrix4(m)
+    }
+}
+
+/// A rotation, as a unit quaternion `w + xi + yj + zk`
+///
+/// `a * b` rotates by `b` first and by `a` after it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion(pub f64, pub f64, pub f64, pub f64);
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion(1.0, 0.0, 0.0, 0.0)
+    }
+
+/// The rotation by `angle` radians around `axis`
+///
+/// Looking along the axis, positive angles turn clockwise, which in the
+/// left-handed world turns the X axis towards the Y axis around Z.
+    pub fn from_axis_angle(axis: Vector3<f64>, angle: f64) -> Quaternion {
+        let a = axis.normalize() * (angle / 2.0).sin();
+        Quaternion((angle / 2.0).cos(), a.0, a.1, a.2)
+    }
+
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion(self.0, -self.1, -self.2, -self.3)
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let Quaternion(w, x, y, z) = *self;
+        let len = (w * w + x * x + y * y + z * z).sqrt();
+        if len > 0.0 {
+            Quaternion(w / len, x / len, y / len, z / len)
+        } else {
+            Quaternion::identity()
+        }
+    }
+
+    pub fn rotate(&self, v: Vector3<f64>) -> Vector3<f64> {
+        let p = *self * Quaternion(0.0, v.0, v.1, v.2) * self.conjugate();
+        Vector3(p.1, p.2, p.3)
+    }
+
+/// The rotation `t` of the way from `self` to `other` along the shortest
+/// arc, `t` being from 0 to 1.
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        let dot = self.0 * other.0 + self.1 * other.1 +
+            self.2 * other.2 + self.3 * other.3;
+        // q and -q are the same rotation; take the one which is closer
+        let (other, dot) = if dot < 0.0 {
+            (Quaternion(-other.0, -other.1, -other.2, -other.3), -dot)
+        } else {
+            (*other, dot)
+        };
+        let (a, b) = if dot > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = dot.acos();
+            (((1.0 - t) * theta).sin() / theta.sin(),
+             (t * theta).sin() / theta.sin())
+        };
+        Quaternion(self.0 * a + other.0 * b, self.1 * a + other.1 * b,
+                   self.2 * a + other.2 * b, self.3 * a + other.3 * b).
+            normalize()
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Quaternion(w, x, y, z) = self.normalize();
+        Matrix4([[1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z),
+                  2.0 * (x * z + w * y), 0.0],
+                 [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z),
+                  2.0 * (y * z - w * x), 0.0],
+                 [2.0 * (x * z - w * y), 2.0 * (y * z + w * x),
+                  1.0 - 2.0 * (x * x + y * y), 0.0],
+                 [0.0, 0.0, 0.0, 1.0]])
+    }
+}
+
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, r: Quaternion) -> Quaternion {
+        let Quaternion(a, b, c, d) = self;
+        Quaternion(a * r.0 - b * r.1 - c * r.2 - d * r.3,
+                   a * r.1 + b * r.0 + c * r.3 - d * r.2,
+                   a * r.2 - b * r.3 + c * r.0 + d * r.1,
+                   a * r.3 + b * r.2 - c * r.1 + d * r.0)
+    }
+}
+
+/// The placement of a model: scaled first, then rotated, then moved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3<f64>,
+    pub rotation: Quaternion,
+    pub scale: Vector3<f64>,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vector3(0.0, 0.0, 0.0),
+            rotation: Quaternion::identity(),
+            scale: Vector3(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn translate(self, v: Vector3<f64>) -> Transform {
+        Transform {
+            translation: self.translation + v,
+            ..self
+        }
+    }
+
+/// Rotate around the origin, after the rotation there already is.
+    pub fn rotate(self, q: Quaternion) -> Transform {
+        Transform {
+            translation: q.rotate(self.translation),
+            rotation: q * self.rotation,
+            ..self
+        }
+    }
+
+/// Scale along the axes of the model.
+    pub fn scale(self, s: Vector3<f64>) -> Transform {
+        Transform {
+            scale: Vector3(self.scale.0 * s.0, self.scale.1 * s.1,
+                           self.scale.2 * s.2),
+            ..self
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        Matrix4::translation(self.translation) * self.rotation.to_matrix() *
+            Matrix4::scaling(self.scale)
     }
 }
 
@@ -95,42 +458,603 @@ impl<T> Vector3<T> {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Primitive {
     TriangleList,
     TriangleStrip
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Vertex {
     pub coords: Vector3<f64>,
     pub color: RGB,
+    /// Texture coordinates, see `Texture::sample`.
+    pub uv: Vector2<f64>,
+    /// The unit vector the surface faces at the vertex.
+    pub normal: Vector3<f64>,
 }
 
+/// A triangle
+///
+/// The front of the triangle is the side its vertices are seen clockwise
+/// from.
 #[derive(Clone, Copy, Debug)]
 pub struct Polygon (pub Vertex, pub Vertex, pub Vertex);
 
+impl Polygon {
+/// A vector perpendicular to the triangle, pointing out of its front.
+    pub fn normal(&self) -> Vector3<f64> {
+        (self.1.coords - self.0.coords) * (self.2.coords - self.0.coords)
+    }
+}
+
+/// Barycentric coordinates of a point of a polygon
+///
+/// The weights of the first, second and third vertex respectively. For
+/// points inside the polygon the weights are non-negative and sum up to one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Barycentric (pub f64, pub f64, pub f64);
+
+impl Barycentric {
+/// Interpolate a per-vertex attribute
+///
+/// * `a`, `b`, `c` - the values of the attribute at the first, second and
+///   third vertex of the polygon.
+    pub fn interpolate<T>(&self, a: T, b: T, c: T) -> T
+        where T: Mul<f64, Output=T> + Add<T, Output=T> {
+            a * self.0 + b * self.1 + c * self.2
+        }
+}
+
+/// The way the surface of a shape looks
+///
+/// Without a texture, the surface has the colour of its vertices. With one,
+/// the texture is tinted by the vertex colours, so white vertices show the
+/// texture as it is.
+///
+/// Shapes are seen from both sides unless `cull_backfaces` is set, in which
+/// case only the front of each polygon is shown. The back still casts
+/// shadows, though.
+///
+/// A surface with `alpha` below 1 lets that share of the light from behind
+/// it through, and its shadows are as faint. The `emission` colour is added
+/// to the surface regardless of the lights, so emissive surfaces glow in the
+/// dark.
+///
+/// A surface with an `outline` is drawn as the outline of its borders while
+/// it hides some part of a surface which is to be `revealed`, such as the
+/// floor behind a wall.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub texture: Option<Rc<Texture>>,
+    pub sampling: Sampling,
+    pub cull_backfaces: bool,
+    pub alpha: f64,
+    pub emission: RGB,
+    pub outline: Option<Outline>,
+    pub revealed: bool,
+}
+
+/// The way a surface looks when the surfaces behind it are shown through it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outline {
+    pub color: RGB,
+    /// The width of the lines along the borders, in world units.
+    pub width: f64,
+    /// How bright the glow around the lines is, 0 being no glow.
+    pub glow: f64,
+}
+
+impl Material {
+    pub fn textured(texture: Rc<Texture>) -> Material {
+        Material {
+            texture: Some(texture),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            texture: None,
+            sampling: Sampling::Nearest,
+            cull_backfaces: false,
+            alpha: 1.0,
+            emission: RGB(0, 0, 0),
+            outline: None,
+            revealed: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Shape {
     pub verts: Vec<Vertex>,
     pub primitive: Primitive,
+    pub material: Material,
 }
 
 impl Shape {
+/// A rectangle facing the camera
+///
+/// * `a`, `b` - the bottom left and the top right corners.
+/// * `uv` - the texture coordinates of the top left and the bottom right
+///   corners.
+    pub fn quad(a: Vector3<f64>, b: Vector3<f64>,
+                uv: (Vector2<f64>, Vector2<f64>),
+                color: RGB, material: Material) -> Shape {
+        let (ta, tb) = uv;
+        let normal = Vector3(0.0, 0.0, -1.0);
+        Shape {
+            verts: vec!(
+                Vertex { coords: Vector3(a.0, b.1, a.2), color: color,
+                         uv: Vector2(ta.0, ta.1), normal: normal },
+                Vertex { coords: Vector3(b.0, b.1, a.2), color: color,
+                         uv: Vector2(tb.0, ta.1), normal: normal },
+                Vertex { coords: Vector3(a.0, a.1, a.2), color: color,
+                         uv: Vector2(ta.0, tb.1), normal: normal },
+                Vertex { coords: Vector3(b.0, a.1, a.2), color: color,
+                         uv: Vector2(tb.0, tb.1), normal: normal }),
+            primitive: Primitive::TriangleStrip,
+            material: material,
+        }
+    }
+
+/// A band of the given width along a line through the points, facing the
+/// camera
+///
+/// The points are taken to lie in a plane of constant Z. The band turns
+/// sharply at the points, so that it looks like a single line, and is empty
+/// for fewer than two distinct points. `v` goes from 0 to 1 along the band.
+    pub fn polyline(points: &[Vector3<f64>], width: f64, color: RGB,
+                    material: Material) -> Shape {
+        let mut points = points.to_vec();
+        points.dedup();
+        let normal = Vector3(0.0, 0.0, -1.0);
+        // To the left of a direction; each pair of vertices starts on the
+        // right, so that the polygons face the camera as those of a quad
+        let side = |d: Vector3<f64>| Vector3(-d.1, d.0, 0.0).normalize();
+        let mut verts = vec!();
+        let n = points.len();
+        for i in 0 .. if n < 2 { 0 } else { n } {
+            let before = side(points[i.max(1)] - points[i.max(1) - 1]);
+            let after = side(points[(i + 1).min(n - 1)] -
+                             points[(i + 1).min(n - 1) - 1]);
+            // Where the edges of the band around two segments meet
+            let mut miter = (before + after).normalize();
+            if miter.dot(before) < 1e-6 {
+                miter = before;
+            }
+            let offset = miter * (width / 2.0 / miter.dot(before));
+            let v = i as f64 / (n - 1) as f64;
+            verts.push(Vertex { coords: points[i] - offset, color: color,
+                                uv: Vector2(0.0, v), normal: normal });
+            verts.push(Vertex { coords: points[i] + offset, color: color,
+                                uv: Vector2(1.0, v), normal: normal });
+        }
+        Shape {
+            verts: verts,
+            primitive: Primitive::TriangleStrip,
+            material: material,
+        }
+    }
+
+/// A copy of the shape with its vertices moved by a transformation
+///
+/// A transformation which mirrors the shape, that is, has a negative
+/// determinant, also turns its polygons the other way.
+    pub fn transformed(&self, m: &Matrix4) -> Shape {
+        Shape {
+            verts: self.verts.iter().map(|v| Vertex {
+                coords: m.transform_point(v.coords),
+                normal: m.transform_normal(v.normal),
+                ..*v
+            }).collect(),
+            primitive: self.primitive,
+            material: self.material.clone(),
+        }
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(self.verts.iter().map(|v| v.coords))
+    }
+
+/// The number of polygons `to_polygons` makes.
+    pub fn polygon_count(&self) -> usize {
+        match self.primitive {
+            Primitive::TriangleList => self.verts.len() / 3,
+            Primitive::TriangleStrip => self.verts.len().saturating_sub(2),
+        }
+    }
+
     pub fn to_polygons(&self) -> Vec<Polygon> {
         match self.primitive {
             Primitive::TriangleList =>  self.verts.chunks (3).map(|x|
                                  Polygon(x[0], x[1], x[2])).collect(),
-            Primitive::TriangleStrip => self.verts.windows(3).map(|x|
-                                 Polygon(x[0], x[1], x[2])).collect()
+            // Every other triangle of a strip has its vertices the other
+            // way round
+            Primitive::TriangleStrip => self.verts.windows(3).enumerate().map(
+                |(i, x)| if i % 2 == 0 {
+                    Polygon(x[0], x[1], x[2])
+                } else {
+                    Polygon(x[1], x[0], x[2])
+                }).collect()
+        }
+    }
+}
+
+/// An axis-aligned box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl Aabb {
+    /// The box containing all the points; `None` if there are none.
+    pub fn from_points<I>(points: I) -> Option<Aabb>
+        where I: IntoIterator<Item=Vector3<f64>> {
+            points.into_iter().fold(None, |acc, p| Some(match acc {
+                None => Aabb { min: p, max: p },
+                Some(b) => b.union(&Aabb { min: p, max: p }),
+            }))
+        }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector3(self.min.0.min(other.min.0),
+                         self.min.1.min(other.min.1),
+                         self.min.2.min(other.min.2)),
+            max: Vector3(self.max.0.max(other.max.0),
+                         self.max.1.max(other.max.1),
+                         self.max.2.max(other.max.2)),
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.0 <= other.max.0 && other.min.0 <= self.max.0 &&
+        self.min.1 <= other.max.1 && other.min.1 <= self.max.1 &&
+        self.min.2 <= other.max.2 && other.min.2 <= self.max.2
+    }
+
+    pub fn corners(&self) -> [Vector3<f64>; 8] {
+        let (a, b) = (self.min, self.max);
+        [Vector3(a.0, a.1, a.2), Vector3(b.0, a.1, a.2),
+         Vector3(a.0, b.1, a.2), Vector3(b.0, b.1, a.2),
+         Vector3(a.0, a.1, b.2), Vector3(b.0, a.1, b.2),
+         Vector3(a.0, b.1, b.2), Vector3(b.0, b.1, b.2)]
+    }
+
+/// The box containing this one after a transformation.
+    pub fn transformed(&self, m: &Matrix4) -> Aabb {
+        Aabb::from_points(self.corners().iter().map(
+            |&c| m.transform_point(c))).unwrap()
+    }
+}
+
+/// A node of a scene graph
+///
+/// The mesh of the node and all its children are placed by `transform`,
+/// relative to the parent node. Meshes are shared, so the same mesh can be
+/// shown by any number of nodes.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub transform: Transform,
+    pub mesh: Option<Rc<Shape>>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn group(transform: Transform) -> Node {
+        Node {
+            transform: transform,
+            mesh: None,
+            children: vec!(),
+        }
+    }
+
+    pub fn instance(mesh: Rc<Shape>, transform: Transform) -> Node {
+        Node {
+            mesh: Some(mesh),
+            ..Node::group(transform)
+        }
+    }
+
+/// The box containing the meshes of the node and of its descendants, `parent`
+/// being the transformation of the parent node to world space.
+    pub fn bounds(&self, parent: &Matrix4) -> Option<Aabb> {
+        let m = *parent * self.transform.to_matrix();
+        let own = self.mesh.as_ref().and_then(|s| s.bounds()).map(
+            |b| b.transformed(&m));
+        self.children.iter().map(|c| c.bounds(&m)).fold(own, |acc, b|
+            match (acc, b) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b),
+            })
+    }
+
+/// Put copies of the meshes of the node and of its descendants into `out`,
+/// moved into world space.
+    pub fn flatten(&self, parent: &Matrix4, out: &mut Vec<Shape>) {
+        self.flatten_where(parent, &|_| true, out);
+    }
+
+/// Like `flatten`, but leaves out the nodes whose bounds `keep` rejects,
+/// along with all their children. Returns the number of polygons left out.
+    pub fn flatten_where<F>(&self, parent: &Matrix4, keep: &F,
+                            out: &mut Vec<Shape>) -> usize
+        where F: Fn(&Aabb) -> bool {
+            let mut summaries = vec!();
+            self.summarize(parent, &mut summaries);
+            self.flatten_summarized(parent, keep, &summaries, 0, out)
+        }
+
+    pub fn polygon_count(&self) -> usize {
+        self.mesh.as_ref().map_or(0, |s| s.polygon_count()) +
+            self.children.iter().map(|c| c.polygon_count()).sum::<usize>()
+    }
+
+/// Push the summaries of the node and of its descendants, each node before
+/// its children, and return that of the node.
+    fn summarize(&self, parent: &Matrix4, out: &mut Vec<Summary>) -> Summary {
+        let m = *parent * self.transform.to_matrix();
+        let i = out.len();
+        let own = self.mesh.as_ref().and_then(|s| s.bounds()).map(
+            |b| b.transformed(&m));
+        out.push(Summary { own: own, bounds: own, polygons: 0, end: i });
+        let mut summary = Summary {
+            own: own,
+            bounds: own,
+            polygons: self.mesh.as_ref().map_or(0, |s| s.polygon_count()),
+            end: i,
+        };
+        for c in self.children.iter() {
+            let child = c.summarize(&m, out);
+            summary.bounds = match (summary.bounds, child.bounds) {
+                (Some(a), Some(b)) => Some(a.union(&b)),
+                (a, b) => a.or(b),
+            };
+            summary.polygons += child.polygons;
+        }
+        summary.end = out.len();
+        out[i] = summary;
+        summary
+    }
+
+/// `flatten_where` for the node whose summary is the `i`th one.
+    fn flatten_summarized<F>(&self, parent: &Matrix4, keep: &F,
+                             summaries: &[Summary], i: usize,
+                             out: &mut Vec<Shape>) -> usize
+        where F: Fn(&Aabb) -> bool {
+            let summary = summaries[i];
+            if !summary.bounds.map_or(false, |b| keep(&b)) {
+                return summary.polygons;
+            }
+            let m = *parent * self.transform.to_matrix();
+            let mut culled = 0;
+            if let Some(ref s) = self.mesh {
+                match summary.own {
+                    Some(b) if keep(&b) => out.push(s.transformed(&m)),
+                    _ => culled += s.polygon_count(),
+                }
+            }
+            let mut j = i + 1;
+            for c in self.children.iter() {
+                culled += c.flatten_summarized(&m, keep, summaries, j, out);
+                j = summaries[j].end;
+            }
+            culled
+        }
+}
+
+/// What `Node::flatten_where` needs to know of a node, found in a single
+/// walk through the scene graph
+#[derive(Clone, Copy, Debug)]
+struct Summary {
+    /// The box containing the mesh of the node, in world space.
+    own: Option<Aabb>,
+    /// The box containing the meshes of the node and of its descendants.
+    bounds: Option<Aabb>,
+    /// The number of polygons of the node and of its descendants.
+    polygons: usize,
+    /// Where the summaries of the descendants end.
+    end: usize,
+}
+
+/// A point light source.
+///
+/// The light affects only the points closer than `radius` to its position,
+/// fading smoothly to nothing at the border.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub position: Vector3<f64>,
+    pub color: RGB,
+    pub radius: f64,
+}
+
+impl Light {
+    /// The box containing every point the light reaches.
+    pub fn bounds(&self) -> Aabb {
+        let r = Vector3(self.radius, self.radius, self.radius);
+        Aabb {
+            min: self.position - r,
+            max: self.position + r,
         }
     }
 }
 
 pub struct World {
+    /// The shapes which are already in world space.
     pub shapes: Vec<Shape>,
-    pub lighting: Vec<Vector3<f64>>,
+    /// The roots of scene graphs, placed in world space.
+    pub nodes: Vec<Node>,
+    pub lighting: Vec<Light>,
+    /// The effects applied to the rendered picture, in order.
+    pub effects: Vec<Effect>,
+}
+
+impl World {
+/// All the shapes of the world in world space, those of the scene graphs
+/// included.
+    pub fn flatten(&self) -> Vec<Shape> {
+        self.flatten_where(|_| true).0
+    }
+
+/// The shapes of the world whose bounds `keep` accepts, and the number of
+/// polygons left out.
+    pub fn flatten_where<F>(&self, keep: F) -> (Vec<Shape>, usize)
+        where F: Fn(&Aabb) -> bool {
+            let mut shapes = vec!();
+            let mut culled = 0;
+            for s in self.shapes.iter() {
+                if s.bounds().map_or(false, |b| keep(&b)) {
+                    shapes.push(s.clone());
+                } else {
+                    culled += s.polygon_count();
+                }
+            }
+            for n in self.nodes.iter() {
+                culled += n.flatten_where(&Matrix4::identity(), &keep,
+                                          &mut shapes);
+            }
+            (shapes, culled)
+        }
 }
 
 pub trait Worldly {
     fn scene(&self) -> World;
+
+    /// The point of view the scene is to be shown from.
+    fn camera(&self) -> Camera {
+        Camera::top_down(640.0, 480.0)
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn close(a: Vector3<f64>, b: Vector3<f64>) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn vector_test() {
+        let v = Vector3(3.0, 0.0, 4.0);
+        assert_eq!(v.length_squared(), 25.0);
+        assert_eq!(v.length(), 5.0);
+        assert!(close(v.normalize(), Vector3(0.6, 0.0, 0.8)));
+        assert_eq!(Vector3(0.0, 0.0, 0.0).normalize(), Vector3(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn rotation_test() {
+        let q = Quaternion::from_axis_angle(Vector3(0.0, 0.0, 1.0), PI / 2.0);
+        let x = Vector3(1.0, 0.0, 0.0);
+        assert!(close(q.rotate(x), Vector3(0.0, 1.0, 0.0)));
+        assert!(close(q.to_matrix().transform_point(x), q.rotate(x)));
+        assert!(close((q * q).rotate(x), Vector3(-1.0, 0.0, 0.0)));
+        let half = Quaternion::identity().slerp(&(q * q), 0.25);
+        assert!(close(half.rotate(x),
+                      Vector3(0.5f64.sqrt(), 0.5f64.sqrt(), 0.0)));
+    }
+
+    #[test]
+    fn transform_test() {
+        let t = Transform::default()
+            .scale(Vector3(2.0, 1.0, 1.0))
+            .translate(Vector3(1.0, 0.0, 0.0))
+            .rotate(Quaternion::from_axis_angle(Vector3(0.0, 0.0, 1.0),
+                                                PI / 2.0));
+        let m = t.to_matrix();
+        // Scaled to (2, 0, 0), moved to (3, 0, 0), rotated to (0, 3, 0)
+        assert!(close(m.transform_point(Vector3(1.0, 0.0, 0.0)),
+                      Vector3(0.0, 3.0, 0.0)));
+        let back = m.inverse().unwrap().transform_point(Vector3(0.0, 3.0, 0.0));
+        assert!(close(back, Vector3(1.0, 0.0, 0.0)));
+        assert!(Matrix4::scaling(Vector3(1.0, 0.0, 1.0)).inverse().is_none());
+
+        // Normals stay perpendicular to stretched surfaces
+        let n = Matrix4::scaling(Vector3(2.0, 1.0, 1.0)).transform_normal(
+            Vector3(1.0, 1.0, 0.0).normalize());
+        let edge = Matrix4::scaling(Vector3(2.0, 1.0, 1.0)).transform_vector(
+            Vector3(1.0, -1.0, 0.0));
+        assert!(n.dot(edge).abs() < 1e-9);
+        assert!((n.length() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn polyline_test() {
+        let line = Shape::polyline(
+            &[Vector3(0.0, 0.0, 1.0), Vector3(0.0, 2.0, 1.0),
+              Vector3(0.0, 2.0, 1.0), Vector3(3.0, 2.0, 1.0)],
+            1.0, RGB(0xff, 0, 0), Material::default());
+        let coords : Vec<Vector3<f64>> = line.verts.iter().map(
+            |v| v.coords).collect();
+        assert_eq!(coords.len(), 6);
+        assert!(close(coords[0], Vector3(0.5, 0.0, 1.0)));
+        assert!(close(coords[1], Vector3(-0.5, 0.0, 1.0)));
+        // The band turns at a right angle, keeping its width
+        assert!(close(coords[2], Vector3(0.5, 1.5, 1.0)));
+        assert!(close(coords[3], Vector3(-0.5, 2.5, 1.0)));
+        assert!(close(coords[5], Vector3(3.0, 2.5, 1.0)));
+        assert_eq!(line.to_polygons().len(), 4);
+        assert_eq!(line.polygon_count(), 4);
+
+        let dot = Shape::polyline(&[Vector3(1.0, 1.0, 1.0)], 1.0,
+                                  RGB(0xff, 0, 0), Material::default());
+        assert!(dot.verts.is_empty());
+    }
+
+    #[test]
+    fn scene_graph_test() {
+        let tile = Rc::new(Shape::quad(
+            Vector3(0.0, 0.0, 0.0), Vector3(1.0, 1.0, 0.0),
+            (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), RGB(0, 0, 0),
+            Material::default()));
+        let at = |x, y| Transform::default().translate(Vector3(x, y, 0.0));
+        let mut row = Node::group(Transform::default().scale(
+            Vector3(2.0, 2.0, 1.0)).translate(Vector3(0.0, 0.0, 5.0)));
+        for i in 0 .. 3 {
+            row.children.push(Node::instance(tile.clone(), at(i as f64, 0.0)));
+        }
+        let world = World {
+            shapes: vec!(),
+            nodes: vec!(row),
+            effects: vec!(),
+            lighting: vec!(),
+        };
+
+        assert_eq!(world.nodes[0].bounds(&Matrix4::identity()), Some(Aabb {
+            min: Vector3(0.0, 0.0, 5.0),
+            max: Vector3(6.0, 2.0, 5.0),
+        }));
+        let shapes = world.flatten();
+        assert_eq!(shapes.len(), 3);
+        assert!(close(shapes[2].verts[1].coords, Vector3(6.0, 2.0, 5.0)));
+        // The mesh is shared rather than copied into every node
+        assert_eq!(Rc::strong_count(&tile), 4);
+
+        // A group left out as a whole is skipped along with its children
+        let mut pair = Node::group(at(4.0, 0.0));
+        pair.children.push(Node::instance(tile.clone(), at(0.0, 0.0)));
+        pair.children.push(Node::instance(tile.clone(), at(0.0, 1.0)));
+        let mut root = Node::group(Transform::default());
+        root.children.push(pair);
+        root.children.extend(world.nodes.iter().cloned());
+        assert_eq!(root.polygon_count(), 10);
+        let view = Aabb {
+            min: Vector3(0.0, 0.0, 0.0),
+            max: Vector3(1.5, 2.0, 5.0),
+        };
+        let mut shapes = vec!();
+        let culled = root.flatten_where(
+            &Matrix4::identity(), &|b: &Aabb| b.intersects(&view),
+            &mut shapes);
+        assert_eq!((shapes.len(), culled), (1, 8));
+        assert!(close(shapes[0].verts[1].coords, Vector3(2.0, 2.0, 5.0)));
+    }
+}
@@ -0,0 +1,180 @@
+//! Golden-image tests of the renderer
+//!
+//! Every test renders a fixed scene and compares the result with a reference
+//! image from `tests/golden`. When some pixels differ by more than
+//! `TOLERANCE`, the rendered image and a picture of the differences are
+//! written into `target/golden` next to each other.
+//!
+//! After an intended change of the output, run the tests with `GOLDEN_UPDATE`
+//! set in the environment to overwrite the reference images.
+
+use camera::Camera;
+use framebuffer::FrameBuffer;
+use geometry::*;
+use render::{Canvas, Antialiasing};
+use screenshot;
+use texture::Texture;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const WIDTH: u16 = 48;
+const HEIGHT: u16 = 36;
+
+/// The largest difference of a channel which isn't considered a mismatch.
+const TOLERANCE: u8 = 2;
+
+fn path(dir: &str, name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(dir).join(name)
+}
+
+fn check(name: &str, scene: &World) {
+    check_antialiased(name, scene, Antialiasing::Grid(1))
+}
+
+fn check_antialiased(name: &str, scene: &World, aa: Antialiasing) {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_antialiasing(aa);
+    canvas.render(scene, &Camera::top_down(WIDTH as f64, HEIGHT as f64));
+    let actual = canvas.pixels();
+
+    let reference = path("tests/golden", &format!("{}.ppm", name));
+    if env::var_os("GOLDEN_UPDATE").is_some() {
+        screenshot::save(actual, &reference).unwrap();
+        return;
+    }
+    let expected = Texture::load(&reference).unwrap_or_else(
+        |e| panic!("{}: {}", reference.display(), e));
+    assert_eq!((expected.width(), expected.height()),
+               (WIDTH as usize, HEIGHT as usize));
+
+    let mut mismatches = 0;
+    let mut diff = FrameBuffer::new(actual.width(), actual.height(),
+                                    RGB(0, 0, 0));
+    for y in 0 .. actual.height() {
+        for x in 0 .. actual.width() {
+            let (a, e) = (actual[(x, y)], expected.texel(x, y));
+            let d = [a.0 as i32 - e.0 as i32,
+                     a.1 as i32 - e.1 as i32,
+                     a.2 as i32 - e.2 as i32].iter().map(
+                         |c| c.abs()).max().unwrap_or(0);
+            diff[(x, y)] = if d > TOLERANCE as i32 {
+                mismatches += 1;
+                RGB(0xff, (0xff - d.min(0xff)) as u8, 0)
+            } else {
+                let grey = ((e.0 as u32 + e.1 as u32 + e.2 as u32) / 6) as u8;
+                RGB(grey, grey, grey)
+            };
+        }
+    }
+
+    if mismatches > 0 {
+        let out = path("target/golden", "");
+        fs::create_dir_all(&out).unwrap();
+        screenshot::save(actual, out.join(format!("{}.actual.ppm", name))).
+            unwrap();
+        screenshot::save(&diff, out.join(format!("{}.diff.ppm", name))).
+            unwrap();
+        panic!("{} of {} pixels of {} differ from {}, see {}", mismatches,
+               WIDTH as usize * HEIGHT as usize, name, reference.display(),
+               out.display());
+    }
+}
+
+/// A grid of cells, `#` being a wall and anything else a floor tile, with
+/// the first line at the top.
+fn tiles(cells: &[&str], size: f64) -> Vec<Shape> {
+    let wall = Material::textured(Texture::asset("wall"));
+    let floor = Material::textured(Texture::asset("floor"));
+    let white = RGB(0xff, 0xff, 0xff);
+    let whole = (Vector2(0.0, 0.0), Vector2(1.0, 1.0));
+    let mut shapes = vec!();
+    for (i, row) in cells.iter().rev().enumerate() {
+        for (j, c) in row.chars().enumerate() {
+            let (x, y) = (j as f64 * size, i as f64 * size);
+            shapes.push(Shape::quad(
+                    Vector3(x, y, 2.0), Vector3(x + size, y + size, 2.0),
+                    whole, white,
+                    if c == '#' { wall.clone() } else { floor.clone() }));
+        }
+    }
+    shapes
+}
+
+#[test]
+fn single_quad() {
+    let mut quad = Shape::quad(Vector3(8.0, 6.0, 2.0),
+                               Vector3(40.0, 30.0, 2.0),
+                               (Vector2(0.0, 0.0), Vector2(1.0, 1.0)),
+                               RGB(0, 0, 0), Material::default());
+    let colors = [RGB(0xff, 0, 0), RGB(0, 0xff, 0),
+                  RGB(0, 0, 0xff), RGB(0xff, 0xff, 0xff)];
+    for (v, &c) in quad.verts.iter_mut().zip(colors.iter()) {
+        v.color = c;
+    }
+    check("single_quad", &World {
+        shapes: vec!(quad),
+        nodes: vec!(),
+        effects: vec!(),
+        lighting: vec!(),
+    });
+}
+
+#[test]
+fn maze_tiles() {
+    check("maze_tiles", &World {
+        shapes: tiles(&["########",
+                        "#  #   #",
+                        "## # # #",
+                        "#    # #",
+                        "# #### #",
+                        "######  "], 6.0),
+        nodes: vec!(),
+        effects: vec!(),
+        lighting: vec!(),
+    });
+}
+
+#[test]
+fn lit_corridor() {
+    check("lit_corridor", &World {
+        shapes: tiles(&["########",
+                        "########",
+                        "        ",
+                        "        ",
+                        "########",
+                        "########"], 6.0),
+        nodes: vec!(),
+        effects: vec!(),
+        lighting: vec!(Light {
+            position: Vector3(9.0, 24.0, 1.0),
+            color: RGB(0xff, 0xc0, 0x80),
+            radius: 30.0,
+        }),
+    });
+}
+
+#[test]
+fn antialiased_edges() {
+    let triangle = |a: (f64, f64), b: (f64, f64), c: (f64, f64), color|
+        Shape {
+            verts: [a, b, c].iter().map(|&(x, y)| Vertex {
+                coords: Vector3(x, y, 2.0),
+                color: color,
+                ..Default::default()
+            }).collect(),
+            primitive: Primitive::TriangleList,
+            material: Material::default(),
+        };
+    let scene = World {
+        shapes: vec!(triangle((2.0, 3.0), (45.0, 12.0), (14.0, 33.0),
+                              RGB(0xff, 0xff, 0xff)),
+                     triangle((30.0, 2.0), (46.0, 34.0), (20.0, 30.0),
+                              RGB(0x20, 0x80, 0xff))),
+        nodes: vec!(),
+        effects: vec!(),
+        lighting: vec!(),
+    };
+    check_antialiased("grid_edges", &scene, Antialiasing::Grid(4));
+    check_antialiased("jittered_edges", &scene, Antialiasing::Jittered(4));
+}
@@ -0,0 +1,344 @@
+//! The keys held down, and the presses they make
+//!
+//! Window systems report a key once when it's pressed and once when it's
+//! released, and may repeat the press on their own while it's held.
+//! `InputState` keeps track of the keys held so that the game doesn't depend
+//! on that repetition: the presses are collected on every step of the game
+//! loop, together with the repeats of the keys held long enough, and the
+//! keys held tell which way the player wants to go.
+//!
+//! `InputLayer` puts the devices together, whatever window system or library
+//! they come from: keys, gamepad buttons and the directions of a stick are
+//! all held and repeated alike, and give the inputs of the game.
+
+use bindings::Bindings;
+use direction::{Direction, DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT};
+use std::f64::consts::PI;
+use geometry::Vector3;
+use tickable::Input;
+
+/// How a held key repeats its press
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Repeat {
+    /// The time from the press to the first repeat, in seconds.
+    pub delay: f64,
+    /// How many times a second the key repeats after that.
+    pub rate: f64,
+}
+
+impl Default for Repeat {
+    fn default() -> Repeat {
+        Repeat {
+            delay: 0.4,
+            rate: 12.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Held<K> {
+    key: K,
+    /// How long the key has been held, in seconds.
+    time: f64,
+    /// How many times the press has been repeated.
+    repeats: u32,
+}
+
+#[derive(Debug)]
+pub struct InputState<K> {
+    /// In the order the keys were pressed.
+    held: Vec<Held<K>>,
+    /// The keys pressed since the last step.
+    pressed: Vec<K>,
+}
+
+impl<K: Clone + PartialEq> InputState<K> {
+    pub fn new() -> InputState<K> {
+        InputState {
+            held: vec!(),
+            pressed: vec!(),
+        }
+    }
+
+/// Record a key going down; the repeated presses of a key which is already
+/// held are left out.
+    pub fn press(&mut self, key: K) {
+        if !self.is_held(&key) {
+            self.held.push(Held { key: key.clone(), time: 0.0, repeats: 0 });
+            self.pressed.push(key);
+        }
+    }
+
+    pub fn release(&mut self, key: &K) {
+        self.held.retain(|h| h.key != *key);
+    }
+
+    pub fn is_held(&self, key: &K) -> bool {
+        self.held.iter().any(|h| h.key == *key)
+    }
+
+/// The keys which act during a step of `dt` seconds
+///
+/// Those are the keys pressed since the last step, even if they've already
+/// been released, then the keys held whose repeat is due, if they `repeat`.
+/// A key repeats at most once a step, so that it doesn't make up for the
+/// repeats missed while it didn't repeat.
+    pub fn step(&mut self, dt: f64, repeat: Option<Repeat>) -> Vec<K> {
+        let mut keys = self.pressed.split_off(0);
+        for h in self.held.iter_mut() {
+            h.time += dt;
+            if let Some(r) = repeat {
+                let due = if h.time < r.delay {
+                    0
+                } else {
+                    1 + ((h.time - r.delay) * r.rate) as u32
+                };
+                if h.repeats < due {
+                    keys.push(h.key.clone());
+                    h.repeats = due;
+                }
+            }
+        }
+        keys
+    }
+
+/// What `f` makes of the keys held for which it gives something, such as
+/// the directions of the arrow keys, the latest pressed last.
+    pub fn held<T, F>(&self, f: F) -> Vec<T> where F: Fn(&K) -> Option<T> {
+        self.held.iter().filter_map(|h| f(&h.key)).collect()
+    }
+}
+
+/// The buttons of a gamepad, named after where they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    /// The lowest of the buttons on the right, like A on an Xbox pad.
+    South,
+    East,
+    North,
+    West,
+    Start,
+    Select,
+    PadUp,
+    PadDown,
+    PadLeft,
+    PadRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    /// The left stick, from -1 on the left to 1 on the right.
+    StickX,
+    /// The left stick, from -1 at the bottom to 1 at the top.
+    StickY,
+}
+
+/// What a device reports.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceEvent {
+    /// A key, by its name in the bindings, is pressed or released.
+    Key(String, bool),
+    Button(Button, bool),
+    Axis(Axis, f64),
+    /// The point of the world a mouse click landed on.
+    Click(Vector3<f64>),
+}
+
+/// Something which is held down and gives an input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Key(String),
+    Button(Button),
+    /// The stick, pushed towards a direction; a stick pushed diagonally
+    /// holds both the horizontal and the vertical one, as two keys would.
+    Stick(Direction),
+}
+
+impl Source {
+/// The input the source gives; keys give the one they're bound to.
+    pub fn input(&self, bindings: &Bindings) -> Option<Input> {
+        match *self {
+            Source::Key(ref name) => bindings.input(name),
+            Source::Button(b) => match b {
+                Button::South => Some(Input::Accept),
+                Button::East => Some(Input::Cancel),
+                Button::Start => Some(Input::Menu),
+                Button::PadUp => Some(Input::Direction(DIR_UP)),
+                Button::PadDown => Some(Input::Direction(DIR_DOWN)),
+                Button::PadLeft => Some(Input::Direction(DIR_LEFT)),
+                Button::PadRight => Some(Input::Direction(DIR_RIGHT)),
+                Button::North | Button::West | Button::Select => None,
+            },
+            Source::Stick(d) => Some(Input::Direction(d)),
+        }
+    }
+}
+
+/// How far a stick has to be pushed to count, as a share of the whole way.
+pub const DEAD_ZONE: f64 = 0.3;
+
+/// The devices, as the sources they hold down and the clicks they make
+#[derive(Debug)]
+pub struct InputLayer {
+    sources: InputState<Source>,
+    /// Where the stick is, on both axes.
+    stick: (f64, f64),
+    pub dead_zone: f64,
+    clicks: Vec<Vector3<f64>>,
+}
+
+impl InputLayer {
+    pub fn new() -> InputLayer {
+        InputLayer {
+            sources: InputState::new(),
+            stick: (0.0, 0.0),
+            dead_zone: DEAD_ZONE,
+            clicks: vec!(),
+        }
+    }
+
+/// The directions the stick is pushed towards, out of the eight ones; none
+/// inside the dead zone, two for the diagonals.
+    fn stick_directions(&self) -> Vec<Direction> {
+        let (x, y) = self.stick;
+        if (x * x + y * y).sqrt() < self.dead_zone {
+            return vec!();
+        }
+        // The nearest of the eighths of a turn from the right
+        let eighths = ((y.atan2(x) / (PI / 4.0)).round() as i32 + 8) % 8;
+        let mut d = DIR_RIGHT;
+        for _ in 0 .. eighths {
+            d = d.rot_ctr_cw();
+        }
+        let (h, v) = d.split();
+        [h, v].iter().cloned().filter(
+            |&p| p == DIR_LEFT || p == DIR_RIGHT || p == DIR_UP ||
+                p == DIR_DOWN).collect()
+    }
+
+    pub fn handle(&mut self, event: DeviceEvent) {
+        match event {
+            DeviceEvent::Key(name, true) =>
+                self.sources.press(Source::Key(name)),
+            DeviceEvent::Key(name, false) =>
+                self.sources.release(&Source::Key(name)),
+            DeviceEvent::Button(b, true) =>
+                self.sources.press(Source::Button(b)),
+            DeviceEvent::Button(b, false) =>
+                self.sources.release(&Source::Button(b)),
+            DeviceEvent::Axis(axis, value) => {
+                let before = self.stick_directions();
+                match axis {
+                    Axis::StickX => self.stick.0 = value,
+                    Axis::StickY => self.stick.1 = value,
+                }
+                // Turning the stick to another direction is like letting go
+                // of a key and pressing another one
+                let after = self.stick_directions();
+                for d in before.iter().filter(|d| !after.contains(d)) {
+                    self.sources.release(&Source::Stick(*d));
+                }
+                for d in after.iter().filter(|d| !before.contains(d)) {
+                    self.sources.press(Source::Stick(*d));
+                }
+            },
+            DeviceEvent::Click(p) => self.clicks.push(p),
+        }
+    }
+
+/// The sources which act during a step; see `InputState::step`.
+    pub fn step(&mut self, dt: f64, repeat: Option<Repeat>) -> Vec<Source> {
+        self.sources.step(dt, repeat)
+    }
+
+    pub fn held<T, F>(&self, f: F) -> Vec<T>
+        where F: Fn(&Source) -> Option<T> {
+            self.sources.held(f)
+        }
+
+/// The points clicked on since the last time.
+    pub fn clicks(&mut self) -> Vec<Vector3<f64>> {
+        self.clicks.split_off(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bindings::Bindings;
+    use direction::{DIR_UP, DIR_LEFT, DIR_RIGHT};
+    use geometry::Vector3;
+    use tickable::Input;
+
+    #[test]
+    fn input_test() {
+        let mut keys = InputState::new();
+        let repeat = Some(Repeat { delay: 0.5, rate: 4.0 });
+        keys.press('a');
+        // A press repeated by the window system counts once
+        keys.press('a');
+        assert_eq!(keys.step(0.125, repeat), vec!('a'));
+        assert_eq!(keys.step(0.25, repeat), vec!());
+        // The first repeat, then one every quarter of a second
+        assert_eq!(keys.step(0.125, repeat), vec!('a'));
+        assert_eq!(keys.step(0.125, repeat), vec!());
+        assert_eq!(keys.step(0.125, repeat), vec!('a'));
+        assert_eq!(keys.step(1.0, None), vec!());
+        assert_eq!(keys.step(0.125, repeat), vec!('a'));
+        keys.release(&'a');
+
+        // A key tapped between two steps isn't lost
+        keys.press('b');
+        keys.release(&'b');
+        assert_eq!(keys.step(0.125, repeat), vec!('b'));
+        assert!(!keys.is_held(&'b'));
+
+        let digit = |k: &char| k.to_digit(10);
+        keys.press('1');
+        keys.press('2');
+        keys.press('c');
+        assert_eq!(keys.held(digit), vec!(1, 2));
+        keys.release(&'2');
+        assert_eq!(keys.held(digit), vec!(1));
+        keys.release(&'1');
+        assert_eq!(keys.held(digit), vec!());
+    }
+
+    #[test]
+    fn layer_test() {
+        let bindings = Bindings::default();
+        let mut layer = InputLayer::new();
+        let inputs = |sources: Vec<Source>| -> Vec<Input> {
+            sources.iter().filter_map(|s| s.input(&bindings)).collect()
+        };
+        layer.handle(DeviceEvent::Key("H".to_string(), true));
+        layer.handle(DeviceEvent::Key("Q".to_string(), true));
+        layer.handle(DeviceEvent::Button(Button::South, true));
+        assert_eq!(inputs(layer.step(0.1, None)),
+                   vec!(Input::Direction(DIR_LEFT), Input::Accept));
+        layer.handle(DeviceEvent::Key("H".to_string(), false));
+
+        // A stick barely pushed doesn't count
+        layer.handle(DeviceEvent::Axis(Axis::StickX, 0.2));
+        assert_eq!(layer.step(0.1, None), vec!());
+        layer.handle(DeviceEvent::Axis(Axis::StickY, 0.9));
+        assert_eq!(layer.step(0.1, None), vec!(Source::Stick(DIR_UP)));
+        let held = |layer: &InputLayer| layer.held(|s| s.input(&bindings));
+        assert_eq!(held(&layer), vec!(Input::Accept, Input::Direction(DIR_UP)));
+
+        // Turning it is another press, letting it go a release; pushed
+        // diagonally, it holds two directions
+        layer.handle(DeviceEvent::Axis(Axis::StickX, 0.95));
+        assert_eq!(layer.step(0.1, None), vec!(Source::Stick(DIR_RIGHT)));
+        layer.handle(DeviceEvent::Axis(Axis::StickY, 0.0));
+        assert_eq!(held(&layer), vec!(Input::Accept,
+                                      Input::Direction(DIR_RIGHT)));
+        layer.handle(DeviceEvent::Axis(Axis::StickX, 0.1));
+        layer.handle(DeviceEvent::Button(Button::South, false));
+        assert_eq!(held(&layer), vec!());
+
+        layer.handle(DeviceEvent::Click(Vector3(1.0, 2.0, 3.0)));
+        assert_eq!(layer.clicks(), vec!(Vector3(1.0, 2.0, 3.0)));
+        assert_eq!(layer.clicks(), vec!());
+    }
+}
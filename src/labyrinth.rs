@@ -1,19 +1,23 @@
 use direction::LeftRight;
 use direction::UpDown;
 use direction::Direction;
+use direction::{DIR_UP,DIR_DOWN,DIR_LEFT,DIR_RIGHT};
+use direction::{DIR_UP_LEFT,DIR_UP_RIGHT,DIR_DOWN_LEFT,DIR_DOWN_RIGHT};
 use std::fmt;
 use std::ops::Index;
 use std::ops::IndexMut;
 use std::collections::LinkedList;
+use std::str::FromStr;
 
-pub struct Field {
-    cells: Vec<Vec<bool>>,
+/// A grid of values, one per cell, by default whether the cell is a wall.
+pub struct Field<T = bool> {
+    cells: Vec<Vec<T>>,
     height: usize,
     width: usize
 }
 
-impl Field {
-    pub fn new(width: usize, height: usize, default: bool) -> Field {
+impl<T: Clone> Field<T> {
+    pub fn new(width: usize, height: usize, default: T) -> Field<T> {
         Field {
             cells: vec![vec![default; width]; height],
             height: height,
@@ -29,10 +33,12 @@ impl Field {
         self.height
     }
 
-    pub fn get(&self, p: Point) -> Option<&bool> {
+    pub fn get(&self, p: Point) -> Option<&T> {
         self.cells.get(p.x).and_then(|k| k.get(p.y))
     }
+}
 
+impl Field {
     pub fn filter_around<T, F>(&self, def: bool, req: bool, p: &Point,
                            t: &mut T, mut f: F)
         where F: FnMut(&mut T, usize, usize, UpDown, LeftRight) {
@@ -74,15 +80,15 @@ impl Field {
     }
 }
 
-impl Index<Point> for Field {
-    type Output = bool;
-    fn index(&self, index: Point) -> &bool {
+impl<T: Clone> Index<Point> for Field<T> {
+    type Output = T;
+    fn index(&self, index: Point) -> &T {
         self.get(index).expect("Out of bounds")
     }
 }
 
-impl IndexMut<Point> for Field {
-    fn index_mut(&mut self, index: Point) -> &mut bool {
+impl<T: Clone> IndexMut<Point> for Field<T> {
+    fn index_mut(&mut self, index: Point) -> &mut T {
         self.cells.index_mut(index.x).index_mut(index.y)
     }
 }
@@ -118,6 +124,64 @@ impl fmt::Display for Field {
     }
 }
 
+/// The ways the player may step from a cell to another
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Movement {
+    /// Up, down, left and right only.
+    FourWay,
+    /// Diagonally as well. A diagonal step goes between the two cells on
+    /// its sides; it may cut the corner of a wall if one of them is open,
+    /// and needs both of them to be open otherwise.
+    EightWay { cut_corners: bool },
+}
+
+impl Movement {
+    pub fn directions(&self) -> &'static [Direction] {
+        const FOUR: [Direction; 4] = [DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT];
+        const EIGHT: [Direction; 8] = [DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT,
+                                       DIR_UP_LEFT, DIR_UP_RIGHT,
+                                       DIR_DOWN_LEFT, DIR_DOWN_RIGHT];
+        match *self {
+            Movement::FourWay => &FOUR,
+            Movement::EightWay { .. } => &EIGHT,
+        }
+    }
+
+    pub fn allows(&self, d: Direction) -> bool {
+        self.directions().contains(&d)
+    }
+}
+
+impl Default for Movement {
+    fn default() -> Movement {
+        Movement::FourWay
+    }
+}
+
+impl fmt::Display for Movement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Movement::FourWay => "four",
+            Movement::EightWay { cut_corners: false } => "eight",
+            Movement::EightWay { cut_corners: true } => "eight-cut",
+        })
+    }
+}
+
+impl FromStr for Movement {
+    type Err = String;
+
+    /// `four`, `eight`, or `eight-cut` for eight ways cutting corners.
+    fn from_str(s: &str) -> Result<Movement, String> {
+        match s {
+            "four" => Ok(Movement::FourWay),
+            "eight" => Ok(Movement::EightWay { cut_corners: false }),
+            "eight-cut" => Ok(Movement::EightWay { cut_corners: true }),
+            _ => Err(format!("unknown movement {}", s)),
+        }
+    }
+}
+
 pub struct Labyrinth(pub Field);
 
 impl fmt::Debug for Labyrinth {
@@ -133,10 +197,67 @@ impl fmt::Display for Labyrinth {
 }
 
 impl Labyrinth {
-    pub fn new(width: usize, height: usize, seed: u16) -> Labyrinth {
-        let mut v = Labyrinth(Field::new(width, height, true));
-        v.fill_labyrinth(seed);
-        v
+/// A new labyrinth, whose corridors are laid out for the way the player
+/// moves
+///
+/// Corridors never touch each other at a corner alone, so that diagonal
+/// steps can't lead through walls. Without corner cutting, the bends of the
+/// corridors are rounded off, or there would be no diagonal steps at all.
+    pub fn new(width: usize, height: usize, seed: u16, movement: Movement)
+        -> Labyrinth {
+            let mut v = Labyrinth(Field::new(width, height, true));
+            v.fill_labyrinth(seed);
+            if movement == (Movement::EightWay { cut_corners: false }) {
+                v.round_corners();
+            }
+            v
+        }
+
+/// Whether the player may step from a cell in a direction.
+    pub fn can_step(&self, from: Point, d: Direction, movement: Movement)
+        -> bool {
+            let open = |p: Point| self.0.get(p) == Some(&false);
+            if !movement.allows(d) || !open(from.neighbor(d)) {
+                return false;
+            }
+            let (h, v) = d.split();
+            match movement {
+                _ if !d.is_diagonal() => true,
+                Movement::EightWay { cut_corners: true } =>
+                    open(from.neighbor(h)) || open(from.neighbor(v)),
+                _ => open(from.neighbor(h)) && open(from.neighbor(v)),
+            }
+        }
+
+/// Open the inner corner of the bends, where it doesn't bring the bend
+/// closer to any other corridor.
+    fn round_corners(&mut self) {
+        let (w, h) = (self.0.width(), self.0.height());
+        for x in 1 .. h.saturating_sub(1) {
+            for y in 1 .. w.saturating_sub(1) {
+                let p = Point { x: x, y: y };
+                if !self.0[p] {
+                    continue;
+                }
+                // The diagonal neighbour in the bend, and the two cells of
+                // the bend next to both
+                let bend = [DIR_UP_LEFT, DIR_UP_RIGHT, DIR_DOWN_LEFT,
+                            DIR_DOWN_RIGHT].iter().map(|&d| {
+                    let (a, b) = d.split();
+                    [p.neighbor(d), p.neighbor(a), p.neighbor(b)]
+                }).find(|cells| cells.iter().all(
+                    |&c| self.0.get(c) == Some(&false)));
+                if let Some(bend) = bend {
+                    let mut en = vec!();
+                    self.0.filter_around(true, false, &p, &mut en,
+                                         |m, x, y, _, _| m.push(
+                                             Point { x: x, y: y }));
+                    if en.len() == 3 && en.iter().all(|c| bend.contains(c)) {
+                        self.0[p] = false;
+                    }
+                }
+            }
+        }
     }
 
     fn fill_labyrinth(&mut self, seed: u16) {
@@ -213,6 +334,36 @@ impl Labyrinth {
              en.iter().any(|x| in_vdir(UpDown::Middle, x)))
     }
 
+    /// Place torches on the wall faces so that no two torches are closer
+    /// than `spacing` cells to each other.
+    ///
+    /// Every wall cell which has a floor cell as an orthogonal neighbour has
+    /// a face looking at that floor cell; a torch is hung on the first such
+    /// face which is far enough from all the torches placed before it.
+    pub fn torches(&self, spacing: usize) -> Vec<Torch> {
+        let mut res : Vec<Torch> = Vec::new();
+        if spacing == 0 {
+            return res;
+        }
+        for x in 0 .. self.0.height() {
+            for y in 0 .. self.0.width() {
+                let p = Point{x: x, y: y};
+                if ! self.0[p] {
+                    continue;
+                }
+                let face = [DIR_UP, DIR_RIGHT, DIR_DOWN, DIR_LEFT].iter().
+                    find(|&&d| self.0.get(p.neighbor(d)) == Some(&false)).
+                    map(|&d| d);
+                if let Some(d) = face {
+                    if res.iter().all(|t| t.wall.distance(&p) >= spacing) {
+                        res.push(Torch { wall: p, facing: d });
+                    }
+                }
+            }
+        }
+        res
+    }
+
     fn empty_neighbors(&self, p: &Point) -> Vec<Direction> {
         let mut res = Vec::new();
         self.0.filter_around(false, false, p, &mut res,
@@ -234,5 +385,81 @@ impl Point {
             y: (self.y as i32 + d.0 as i32) as usize
         }
     }
+
+    /// The number of king moves needed to get from one point to another.
+    pub fn distance(&self, other: &Point) -> usize {
+        let dx = (self.x as i32 - other.x as i32).abs();
+        let dy = (self.y as i32 - other.y as i32).abs();
+        if dx > dy { dx as usize } else { dy as usize }
+    }
+}
+
+/// A static light source hung on a wall.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Torch {
+    /// The wall cell the torch is attached to.
+    pub wall: Point,
+    /// The side of the wall the torch is on, pointing towards the floor.
+    pub facing: Direction,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use direction::{DIR_UP, DIR_DOWN, DIR_LEFT, DIR_RIGHT, DIR_UP_RIGHT};
+
+    #[test]
+    fn movement_test() {
+        let mut l = Labyrinth(Field::new(4, 4, true));
+        for &(x, y) in [(1, 1), (1, 2), (2, 2)].iter() {
+            l.0[Point { x: x, y: y }] = false;
+        }
+        let p = Point { x: 1, y: 1 };
+        let cut = Movement::EightWay { cut_corners: true };
+        let round = Movement::EightWay { cut_corners: false };
+        assert!(l.can_step(p, DIR_RIGHT, Movement::FourWay));
+        assert!(!l.can_step(p, DIR_UP, cut));
+        assert!(!l.can_step(p, DIR_UP_RIGHT, Movement::FourWay));
+        assert!(l.can_step(p, DIR_UP_RIGHT, cut));
+        assert!(!l.can_step(p, DIR_UP_RIGHT, round));
+
+        for seed in 0 .. 4 {
+            let l = Labyrinth::new(30, 20, seed, round);
+            let open = |x, y| !l.0[Point { x: x, y: y }];
+            let mut rounded = 0;
+            for x in 0 .. 19 {
+                for y in 0 .. 29 {
+                    // Corridors only meet at a corner through a third cell
+                    assert!(!(open(x, y) && open(x + 1, y + 1)) ||
+                            open(x + 1, y) || open(x, y + 1));
+                    assert!(!(open(x + 1, y) && open(x, y + 1)) ||
+                            open(x, y) || open(x + 1, y + 1));
+                    if open(x, y) && open(x + 1, y + 1) && open(x + 1, y) &&
+                        open(x, y + 1) {
+                            rounded += 1;
+                        }
+                }
+            }
+            assert!(rounded > 0);
+        }
+    }
+
+    #[test]
+    fn torches_test() {
+        let l = Labyrinth::new(30, 20, 3, Movement::FourWay);
+        let torches = l.torches(4);
+        assert!(!torches.is_empty());
+        for (i, t) in torches.iter().enumerate() {
+            // On a wall, looking straight at the floor
+            assert!(l.0[t.wall]);
+            assert!([DIR_UP, DIR_RIGHT, DIR_DOWN, DIR_LEFT].contains(
+                &t.facing));
+            assert_eq!(l.0.get(t.wall.neighbor(t.facing)), Some(&false));
+            for u in torches[.. i].iter() {
+                assert!(u.wall.distance(&t.wall) >= 4);
+            }
+        }
+        assert!(l.torches(0).is_empty());
+    }
+}
@@ -9,39 +9,59 @@ mod license;
 mod screen;
 mod geometry;
 mod render;
+mod texture;
+mod camera;
+mod screenshot;
+mod framebuffer;
+mod obj;
+mod animation;
+mod postprocess;
+mod input;
+mod bindings;
+mod present;
+#[cfg(test)]
+mod golden;
 
 #[macro_use]
 extern crate glium;
 
 use std::time::{Duration, Instant};
 use std::thread;
+use std::env;
+use std::process;
 
-use glium::{DisplayBuild, Surface};
+use glium::DisplayBuild;
 use glium::glutin;
 
 use glium::glutin::VirtualKeyCode;
 
 use tickable::Input;
 use screen::Screen;
+use input::{DeviceEvent, InputLayer, Source};
 use direction::{DIR_UP,DIR_DOWN,DIR_LEFT,DIR_RIGHT};
+use direction::{DIR_UP_LEFT,DIR_UP_RIGHT,DIR_DOWN_LEFT,DIR_DOWN_RIGHT};
 
 enum Action {
     Continue,
-    Key(glutin::VirtualKeyCode),
     Stop
 }
 
+/// The name of a key in the bindings, such as `H` or `Left`.
+fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
 // Taken from glium.git/examples/support/mod.rs
 fn start_loop<F>(scr: &mut Box<Screen>, mut callback: F)
-    where F: FnMut(&geometry::World) -> Action {
+    where F: FnMut(&geometry::World, &camera::Camera,
+                   &mut InputLayer) -> Action {
         let mut accumulator = Duration::new(0, 0);
         let mut previous_clock = Instant::now();
+        let mut devices = InputLayer::new();
 
         loop {
-            let mut key = None;
-            match callback(&scr.scene()) {
+            match callback(&scr.scene(), &scr.camera(), &mut devices) {
                 Action::Stop => break,
-                Action::Key(k) => key = Some(k),
                 Action::Continue => ()
             };
 
@@ -54,21 +74,28 @@ fn start_loop<F>(scr: &mut Box<Screen>, mut callback: F)
                 accumulator -= fixed_time_stamp;
 
                 // Modifying the state of the game
-                if let Some(b) = key {
-                    let ev = match b {
-                        VirtualKeyCode::H => Some(Input::Direction(DIR_LEFT)),
-                        VirtualKeyCode::J => Some(Input::Direction(DIR_DOWN)),
-                        VirtualKeyCode::K => Some(Input::Direction(DIR_UP)),
-                        VirtualKeyCode::L => Some(Input::Direction(DIR_RIGHT)),
-                        VirtualKeyCode::Z => Some(Input::Accept),
-                        VirtualKeyCode::X => Some(Input::Cancel),
-                        VirtualKeyCode::Colon => Some(Input::Menu),
-                        _ => None
-                    };
-                    if let Some(f) = scr.tick(ev) {
-                        *scr = f;
+                let dt = fixed_time_stamp.subsec_nanos() as f64 * 1e-9;
+                for p in devices.clicks() {
+                    scr.click(p);
+                }
+                for s in devices.step(dt, scr.repeat()) {
+                    if let Source::Key(ref name) = s {
+                        if scr.key(name) {
+                            println!("{:?}", scr);
+                            continue;
+                        }
                     }
-                    println!("{:?}", scr);
+                    if let Some(ev) = s.input(scr.bindings()) {
+                        if let Some(f) = scr.tick(Some(ev)) {
+                            *scr = f;
+                        }
+                        println!("{:?}", scr);
+                    }
+                }
+                let held = devices.held(|s| s.input(scr.bindings()));
+                scr.hold(&held);
+                if let Some(f) = scr.advance(fixed_time_stamp) {
+                    *scr = f;
                 }
             }
 
@@ -76,36 +103,164 @@ fn start_loop<F>(scr: &mut Box<Screen>, mut callback: F)
         }
 }
 
+const USAGE: &'static str = "\
+Usage: rusty-door [--screenshot FILE [OPTIONS]]
+
+Without arguments, starts the game. With --screenshot, renders a frame of a
+new game into FILE (PNG if it ends with .png, PPM otherwise) and exits
+without opening a window.
+
+Options:
+    --seed N          the seed of the labyrinth
+    --width N         the width of the labyrinth, in cells
+    --height N        the height of the labyrinth, in cells
+    --size WxH        the size of the image, in pixels
+    --antialiasing AA the rays per pixel: grid:N or jitter:N for N by N rays
+    --moves KEYS      moves to make before rendering, as h, j, k, l keys,
+                      or y, u, b, n for the diagonals
+    --movement MODE   four, eight, or eight-cut to cut the corners of walls
+    --stats           print how many polygons were drawn and culled";
+
+fn parse<T: std::str::FromStr>(opt: &str, value: Option<&String>)
+    -> Result<T, String> {
+        value.ok_or(format!("{} needs a value", opt)).and_then(
+            |v| v.parse().map_err(|_| format!("bad value of {}: {}", opt, v)))
+    }
+
+/// Render a single frame into a file without opening a window.
+fn headless(args: &[String]) -> Result<(), String> {
+    let mut state = state::ProgramState::new();
+    let mut output = None;
+    let mut size = (640, 480);
+    let mut aa = render::Antialiasing::Grid(1);
+    let mut moves = String::new();
+    let mut stats = false;
+
+    let mut it = args.iter();
+    while let Some(opt) = it.next() {
+        match &opt[..] {
+            "--screenshot" => output = Some(try!(parse::<String>(opt,
+                                                                 it.next()))),
+            "--seed"   => state.seed   = Some(try!(parse(opt, it.next()))),
+            "--width"  => state.width  = try!(parse(opt, it.next())),
+            "--height" => state.height = try!(parse(opt, it.next())),
+            "--moves"  => moves = try!(parse(opt, it.next())),
+            "--movement" => state.movement = try!(parse(opt, it.next())),
+            "--antialiasing" => aa = try!(parse(opt, it.next())),
+            "--stats" => stats = true,
+            "--size" => {
+                let v : String = try!(parse(opt, it.next()));
+                let wh : Vec<&str> = v.split('x').collect();
+                size = match (wh.get(0).and_then(|w| w.parse().ok()),
+                              wh.get(1).and_then(|h| h.parse().ok())) {
+                    (Some(w), Some(h)) if wh.len() == 2 && w > 0 && h > 0 =>
+                        (w, h),
+                    _ => return Err(format!("bad value of --size: {}", v)),
+                };
+            },
+            _ => return Err(format!("unknown option {}", opt)),
+        }
+    }
+    let output = try!(output.ok_or("--screenshot is required".to_string()));
+
+    state.new_game();
+    let mut scr : Box<Screen> = Box::new(play::PlayScreen::new(state));
+    for m in moves.chars() {
+        let d = match m {
+            'h' => DIR_LEFT,
+            'j' => DIR_DOWN,
+            'k' => DIR_UP,
+            'l' => DIR_RIGHT,
+            'y' => DIR_UP_LEFT,
+            'u' => DIR_UP_RIGHT,
+            'b' => DIR_DOWN_LEFT,
+            'n' => DIR_DOWN_RIGHT,
+            _ => return Err(format!("unknown move {}", m)),
+        };
+        if let Some(f) = scr.tick(Some(Input::Direction(d))) {
+            scr = f;
+        }
+        // Long enough for the step to be over, as in the game loop
+        for _ in 0 .. 30 {
+            if let Some(f) = scr.advance(Duration::new(0, 16666667)) {
+                scr = f;
+            }
+        }
+    }
+
+    let mut canvas = render::Canvas::new(size.0, size.1);
+    canvas.set_antialiasing(aa);
+    canvas.render(&scr.scene(),
+                  &scr.camera().with_aspect(size.0 as f64 / size.1 as f64));
+    if stats {
+        println!("{}", canvas.stats());
+    }
+    screenshot::save(canvas.pixels(), &output).map_err(
+        |e| format!("can't write {}: {}", output, e))
+}
+
 fn main() {
+    let args : Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = headless(&args) {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+        return;
+    }
+
     let display = glutin::WindowBuilder::new()
         .with_vsync()
         .build_glium()
         .unwrap();
 
-    let pr = state::ProgramState::new();
+    let mut pr = state::ProgramState::new();
+    match bindings::Bindings::load() {
+        Ok(b) => pr.bindings = b,
+        Err(e) => eprintln!("{}; using the default keys", e),
+    }
     let mut scr : Box<Screen> = Box::new(menu::MenuScreen::new(pr));
-    start_loop(&mut scr, |scene| {
+    // Kept between frames, so that only what has changed is traced again
+    let (width, height) = display.get_framebuffer_dimensions();
+    let mut canvas = render::Canvas::new(width as u16, height as u16);
+    let mut presenter = present::Presenter::new(&display, width, height).
+        unwrap();
+    // Where the mouse is, in pixels from the top left corner of the window
+    let mut mouse = (0, 0);
+    start_loop(&mut scr, |scene, camera, devices| {
         for event in display.poll_events() {
             match event {
                 glutin::Event::Closed => return Action::Stop,
-                glutin::Event::KeyboardInput(
+                glutin::Event::Resized(w, h) => {
+                    let (w, h) = (w.max(1).min(0xffff), h.max(1).min(0xffff));
+                    presenter.resize(&display, w, h).unwrap();
+                    canvas.resize(w as u16, h as u16);
+                },
+                glutin::Event::KeyboardInput(state, _, Some(key)) =>
+                    devices.handle(DeviceEvent::Key(
+                        key_name(key),
+                        state == glutin::ElementState::Pressed)),
+                glutin::Event::MouseMoved(x, y) => mouse = (x, y),
+                glutin::Event::MouseInput(
                     glutin::ElementState::Pressed,
-                    _,
-                    Some(key)
-                    ) => return Action::Key(key),
+                    glutin::MouseButton::Left
+                    ) => {
+                    // On what was shown when the button was pressed
+                    let (x, y) = (mouse.0 as f64 + 0.5, mouse.1 as f64 + 0.5);
+                    if let Some(p) = canvas.pick(x, y) {
+                        devices.handle(DeviceEvent::Click(p));
+                    }
+                },
                 _ => ()
             }
         }
-        let mut canvas = render::Canvas::new(640, 480);
-        canvas.render(scene);
-        let pixels : Vec<Vec<(u8, u8, u8)>> = canvas.pixels().iter().map(
-            |r| r.iter().map(
-                |&geometry::RGB(r, g, b)| (r, g, b)).collect()).collect();
-        let opengl_texture = glium::Texture2d::new(&display, pixels).unwrap();
+        // The picture is widened or narrowed to the shape of the window
+        let (w, h) = canvas.size();
+        canvas.render(scene, &camera.with_aspect(w as f64 / h as f64));
+        presenter.upload(canvas.pixels());
 
         let target = display.draw();
-        opengl_texture.as_surface().fill(
-            &target, glium::uniforms::MagnifySamplerFilter::Linear);
+        presenter.draw(&target);
         target.finish().unwrap();
         Action::Continue
     });
@@ -1,6 +1,8 @@
 use tickable::Input;
 use screen::Screen;
 use state::ProgramState;
+use bindings::{Bindings, Control};
+use labyrinth::Movement;
 use direction;
 use license;
 use play::PlayScreen;
@@ -20,6 +22,9 @@ enum Subscreens {
     HighScore,
     License(u16),
     Quit(Menu, bool),
+    /// The control whose keys are shown, and whether the next key pressed
+    /// is bound to it.
+    Keys(Control, bool),
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -27,12 +32,25 @@ enum Options {
     Width,
     Height,
     Seed,
+    TorchSpacing,
+    Movement,
+    Keys,
 }
 
-const OPTION_ITEMS: [Options; 3] = [
+const OPTION_ITEMS: [Options; 6] = [
     Options::Width,
     Options::Height,
     Options::Seed,
+    Options::TorchSpacing,
+    Options::Movement,
+    Options::Keys,
+];
+
+/// The ways of moving, in the order the options go through them.
+const MOVEMENTS: [Movement; 3] = [
+    Movement::FourWay,
+    Movement::EightWay { cut_corners: false },
+    Movement::EightWay { cut_corners: true },
 ];
 
 impl Options {
@@ -90,7 +108,9 @@ impl geometry::Worldly for MenuScreen {
     fn scene(&self) -> geometry::World {
         geometry::World {
             shapes   : vec!(),
-            lighting : vec!()
+            nodes    : vec!(),
+            lighting : vec!(),
+            effects  : vec!()
         }
     }
 }
@@ -118,6 +138,10 @@ impl Screen for MenuScreen {
                 Subscreens::Quit(m, q) => {
                     self.tick_quit(i, m, q);
                     None
+                },
+                Subscreens::Keys(c, _) => {
+                    self.tick_keys(i, c);
+                    None
                 }
             }
         } else {
@@ -125,6 +149,27 @@ impl Screen for MenuScreen {
         }
     }
 
+    fn bindings(&self) -> &Bindings {
+        &self.state.bindings
+    }
+
+    /// Escape always leaves the keys, whatever it's bound to, so that they
+    /// can't be bound in a way which keeps the player there.
+    fn key(&mut self, key: &str) -> bool {
+        match self.subscreen {
+            Subscreens::Keys(c, true) => {
+                self.state.bindings.bind(c, key);
+                self.subscreen = Subscreens::Keys(c, false);
+                true
+            },
+            Subscreens::Keys(_, false) if key == "Escape" => {
+                self.leave_keys();
+                true
+            },
+            _ => false
+        }
+    }
+
 }
 
 impl MenuScreen {
@@ -216,7 +261,16 @@ impl MenuScreen {
                             Options::Seed => {
                                 self.state.seed =
                                     self.state.seed.map(|x| x - 1)
-                            }
+                            },
+                            Options::TorchSpacing => {
+                                if self.state.torch_spacing > 0 {
+                                    self.state.torch_spacing =
+                                        self.state.torch_spacing - 1
+                                }
+                            },
+                            Options::Movement =>
+                                self.state.movement = self.movement(-1),
+                            Options::Keys => (),
                         }
                     } else if d == direction::DIR_RIGHT {
                         match o {
@@ -227,10 +281,19 @@ impl MenuScreen {
                             Options::Seed => {
                                 self.state.seed =
                                     self.state.seed.map(|x| x + 1)
-                            }
+                            },
+                            Options::TorchSpacing =>
+                                self.state.torch_spacing =
+                                    self.state.torch_spacing + 1,
+                            Options::Movement =>
+                                self.state.movement = self.movement(1),
+                            Options::Keys => (),
                         }
                     }
                 },
+                Input::Accept if o == Options::Keys => {
+                    self.subscreen = Subscreens::Keys(Control::Up, false);
+                },
                 Input::Cancel => {
                     self.subscreen = Subscreens::Menu(Menu::Options);
                 },
@@ -238,6 +301,44 @@ impl MenuScreen {
             }
         }
 
+    /// The way of moving `by` places from the current one, among
+    /// `MOVEMENTS`.
+    fn movement(&self, by: isize) -> Movement {
+        let n = MOVEMENTS.len() as isize;
+        let i = MOVEMENTS.iter().position(
+            |&m| m == self.state.movement).unwrap_or(0) as isize;
+        MOVEMENTS[((i + by) % n + n) as usize % MOVEMENTS.len()]
+    }
+
+    /// Up and down go through the controls, accept waits for a key to bind
+    /// to one, left gives it back its default keys. The bindings are saved
+    /// when leaving.
+    fn tick_keys(&mut self, input: Input, c: Control) {
+        match input {
+            Input::Direction(d) => {
+                if d == direction::DIR_DOWN {
+                    self.subscreen = Subscreens::Keys(c.next(), false);
+                } else if d == direction::DIR_UP {
+                    self.subscreen = Subscreens::Keys(c.prev(), false);
+                } else if d == direction::DIR_LEFT {
+                    self.state.bindings.reset(c);
+                }
+            },
+            Input::Accept => {
+                self.subscreen = Subscreens::Keys(c, true);
+            },
+            Input::Cancel => self.leave_keys(),
+            _ => ()
+        }
+    }
+
+    fn leave_keys(&mut self) {
+        if let Err(e) = self.state.bindings.save() {
+            eprintln!("can't save key bindings: {}", e);
+        }
+        self.subscreen = Subscreens::Options(Options::Keys);
+    }
+
     fn tick_license(&mut self, input: Input, line: u16) {
         match input {
             Input::Direction(d) => {
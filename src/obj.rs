@@ -0,0 +1,432 @@
+//! Loading of meshes in the Wavefront OBJ format
+//!
+//! Supported are vertex positions, texture coordinates and normals, polygonal
+//! faces, which are split into triangles, and materials from MTL files with a
+//! diffuse colour and a diffuse texture. Anything else, such as groups or
+//! smoothing, is skipped.
+//!
+//! OBJ files use right-handed coordinates, so the Z axis is flipped to fit the
+//! left-handed world, and the order of the vertices of every face is reversed
+//! to keep it facing the same way.
+
+use geometry::*;
+use texture::{Texture, TextureError};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    /// A line of an OBJ or MTL file which can't be understood.
+    Syntax {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    Texture(String, TextureError),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref e) => write!(f, "can't read model: {}", e),
+            ObjError::Syntax { ref file, line, ref message } =>
+                write!(f, "{}:{}: {}", file, line, message),
+            ObjError::Texture(ref name, ref e) =>
+                write!(f, "{}: {}", name, e),
+        }
+    }
+}
+
+impl error::Error for ObjError {
+    fn description(&self) -> &str {
+        match *self {
+            ObjError::Io(_) => "can't read model",
+            ObjError::Syntax { .. } => "bad model",
+            ObjError::Texture(..) => "can't load the texture of a model",
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(e: io::Error) -> ObjError {
+        ObjError::Io(e)
+    }
+}
+
+/// A material of an MTL file: the texture and the colour it's tinted with.
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub color: RGB,
+    pub material: Material,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> ObjMaterial {
+        ObjMaterial {
+            color: RGB(0xff, 0xff, 0xff),
+            material: Material::default(),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    let mut src = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut src));
+    Ok(src)
+}
+
+/// Load a model, along with the MTL files and the textures it refers to
+///
+/// The paths in the OBJ file are relative to the directory of the file. The
+/// model is made of one shape per material.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Shape>, ObjError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let src = try!(read_file(path));
+    parse_obj(&path.display().to_string(), &src, |name| {
+        let mtl = dir.join(name);
+        parse_mtl(&mtl.display().to_string(), &try!(read_file(&mtl)),
+                  |texture| Texture::load(dir.join(texture)).map(Rc::new))
+    })
+}
+
+/// A cursor over the words of a line.
+struct Line<'a> {
+    file: &'a str,
+    number: usize,
+    words: ::std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Line<'a> {
+    fn error<T>(&self, message: String) -> Result<T, ObjError> {
+        Err(ObjError::Syntax {
+            file: self.file.to_string(),
+            line: self.number,
+            message: message,
+        })
+    }
+
+    fn word(&mut self, what: &str) -> Result<&'a str, ObjError> {
+        match self.words.next() {
+            Some(w) => Ok(w),
+            None => self.error(format!("expected {}", what)),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, ObjError> {
+        let w = try!(self.word("a number"));
+        match w.parse() {
+            Ok(x) => Ok(x),
+            Err(_) => self.error(format!("expected a number, got {:?}", w)),
+        }
+    }
+
+    /// The rest of the line, which is a name that may contain spaces.
+    fn name(&mut self, what: &str) -> Result<String, ObjError> {
+        let words : Vec<&str> = self.words.by_ref().collect();
+        if words.is_empty() {
+            self.error(format!("expected {}", what))
+        } else {
+            Ok(words.join(" "))
+        }
+    }
+}
+
+/// Split a file into lines without comments, numbered from 1, and pass them
+/// along with their first word to `f`.
+fn each_line<F>(file: &str, src: &str, mut f: F) -> Result<(), ObjError>
+    where F: FnMut(&str, &mut Line) -> Result<(), ObjError> {
+        for (i, text) in src.lines().enumerate() {
+            let text = text.split('#').next().unwrap_or("");
+            let mut line = Line {
+                file: file,
+                number: i + 1,
+                words: text.split_whitespace(),
+            };
+            if let Some(keyword) = line.words.next() {
+                try!(f(keyword, &mut line));
+            }
+        }
+        Ok(())
+    }
+
+/// Read the materials of an MTL file
+///
+/// * `texture` - loads the texture of the given name.
+pub fn parse_mtl<F>(file: &str, src: &str, mut texture: F)
+    -> Result<HashMap<String, ObjMaterial>, ObjError>
+    where F: FnMut(&str) -> Result<Rc<Texture>, TextureError> {
+        let mut materials = HashMap::new();
+        let mut current : Option<(String, ObjMaterial)> = None;
+        try!(each_line(file, src, |keyword, line| {
+            if keyword == "newmtl" {
+                let name = try!(line.name("the name of the material"));
+                if let Some((n, m)) = current.take() {
+                    materials.insert(n, m);
+                }
+                current = Some((name, ObjMaterial::default()));
+                return Ok(());
+            }
+            let m = match current {
+                Some((_, ref mut m)) => m,
+                None if keyword == "Kd" || keyword == "map_Kd" =>
+                    return line.error("no material defined yet".to_string()),
+                None => return Ok(()),
+            };
+            match keyword {
+                "Kd" => {
+                    let (r, g, b) = (try!(line.number()), try!(line.number()),
+                                     try!(line.number()));
+                    m.color = RGBf(r, g, b).to_srgb();
+                },
+                "map_Kd" => {
+                    // Options of the texture come before its name
+                    let name = try!(line.name("the name of the texture"));
+                    let name = name.split_whitespace().last().unwrap_or("");
+                    m.material.texture = Some(try!(texture(name).map_err(
+                        |e| ObjError::Texture(name.to_string(), e))));
+                },
+                _ => {},
+            }
+            Ok(())
+        }));
+        if let Some((n, m)) = current {
+            materials.insert(n, m);
+        }
+        Ok(materials)
+    }
+
+/// The index of an element of a list from an index of an OBJ file, which
+/// starts at 1, or counts back from the end of the list if it's negative.
+fn resolve(line: &Line, index: &str, len: usize, what: &str)
+    -> Result<usize, ObjError> {
+        let i : i64 = match index.parse() {
+            Ok(i) => i,
+            Err(_) => return line.error(
+                format!("expected the index of a {}, got {:?}", what, index)),
+        };
+        let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            line.error(format!("no {} number {}", what, i))
+        } else {
+            Ok(resolved as usize)
+        }
+    }
+
+/// Read the shapes of an OBJ file
+///
+/// * `mtllib` - reads the materials of the MTL file of the given name.
+pub fn parse_obj<F>(file: &str, src: &str, mut mtllib: F)
+    -> Result<Vec<Shape>, ObjError>
+    where F: FnMut(&str) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+        let mut positions = vec!();
+        let mut uvs = vec!();
+        let mut normals = vec!();
+        let mut materials = HashMap::new();
+        // The shapes by the name of their material, in order of appearance
+        let mut shapes : Vec<(String, Shape)> = vec!();
+        let mut current = String::new();
+
+        try!(each_line(file, src, |keyword, line| {
+            match keyword {
+                "v" => {
+                    let (x, y, z) = (try!(line.number()), try!(line.number()),
+                                     try!(line.number()));
+                    positions.push(Vector3(x, y, -z));
+                },
+                "vt" => {
+                    let u = try!(line.number());
+                    // The second coordinate is optional
+                    let v = match line.words.next() {
+                        Some(v) => match v.parse() {
+                            Ok(v) => v,
+                            Err(_) => return line.error(
+                                format!("expected a number, got {:?}", v)),
+                        },
+                        None => 0.0,
+                    };
+                    // OBJ textures start with the bottom row
+                    uvs.push(Vector2(u, 1.0 - v));
+                },
+                "vn" => {
+                    let (x, y, z) = (try!(line.number()), try!(line.number()),
+                                     try!(line.number()));
+                    let n = Vector3(x, y, -z);
+                    if n.length() == 0.0 {
+                        return line.error("zero normal".to_string());
+                    }
+                    normals.push(n.normalize());
+                },
+                "f" => {
+                    let mut verts = vec!();
+                    while let Some(w) = line.words.next() {
+                        let parts : Vec<&str> = w.split('/').collect();
+                        if parts.len() > 3 {
+                            return line.error(
+                                format!("bad face vertex {:?}", w));
+                        }
+                        let mut v = Vertex {
+                            coords: positions[try!(resolve(
+                                line, parts[0], positions.len(), "vertex"))],
+                            ..Default::default()
+                        };
+                        match parts.get(1) {
+                            Some(&"") | None => {},
+                            Some(i) => v.uv = uvs[try!(resolve(
+                                line, i, uvs.len(), "texture vertex"))],
+                        }
+                        match parts.get(2) {
+                            Some(&"") | None => {},
+                            Some(i) => v.normal = normals[try!(resolve(
+                                line, i, normals.len(), "normal"))],
+                        }
+                        verts.push(v);
+                    }
+                    if verts.len() < 3 {
+                        return line.error(
+                            "a face needs at least 3 vertices".to_string());
+                    }
+                    let m = if current.is_empty() {
+                        ObjMaterial::default()
+                    } else {
+                        materials.get(&current).cloned().unwrap()
+                    };
+                    let shape = match shapes.iter().position(
+                        |&(ref n, _)| *n == current) {
+                        Some(i) => &mut shapes[i].1,
+                        None => {
+                            shapes.push((current.clone(), Shape {
+                                verts: vec!(),
+                                primitive: Primitive::TriangleList,
+                                material: m.material.clone(),
+                            }));
+                            &mut shapes.last_mut().unwrap().1
+                        }
+                    };
+                    // A fan around the first vertex, which only works for
+                    // convex polygons, as OBJ faces are supposed to be
+                    for i in 1 .. verts.len() - 1 {
+                        for &j in [0, i + 1, i].iter() {
+                            shape.verts.push(Vertex {
+                                color: m.color,
+                                ..verts[j]
+                            });
+                        }
+                    }
+                },
+                "mtllib" => {
+                    let name = try!(line.name("the name of an MTL file"));
+                    materials.extend(try!(mtllib(&name)));
+                },
+                "usemtl" => {
+                    let name = try!(line.name("the name of a material"));
+                    if !materials.contains_key(&name) {
+                        return line.error(
+                            format!("unknown material {:?}", name));
+                    }
+                    current = name;
+                },
+                _ => {},
+            }
+            Ok(())
+        }));
+        Ok(shapes.into_iter().map(|(_, s)| s).collect())
+    }
+
+#[cfg(test)]
+mod tests {
+    use geometry::*;
+    use texture::Texture;
+    use super::{parse_obj, parse_mtl, ObjError};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    const MTL: &'static str = "
+newmtl red
+Kd 1.0 0.0 0.0
+newmtl skin
+Kd 1 1 1
+map_Kd -clamp on skin.ppm
+";
+
+    #[test]
+    fn obj_test() {
+        let obj = "
+# a quad and a triangle
+mtllib model.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 1
+vt 0 0
+vt 1 1
+vn 0 0 1
+usemtl red
+f 1/1/1 2/1/1 3/2/1 4//1
+usemtl skin
+f -3 -2 -1
+";
+        let mut loaded = vec!();
+        let shapes = parse_obj("model.obj", obj, |name| {
+            parse_mtl(name, MTL, |texture| {
+                loaded.push(texture.to_string());
+                Ok(Rc::new(Texture::missing()))
+            })
+        }).unwrap();
+        assert_eq!(loaded, vec!("skin.ppm"));
+        assert_eq!(shapes.len(), 2);
+
+        // The quad is split into two triangles
+        let quad = &shapes[0];
+        assert_eq!(quad.verts.len(), 6);
+        assert!(quad.material.texture.is_none());
+        let v = quad.verts[0];
+        assert_eq!(v.color, RGB(0xff, 0, 0));
+        assert_eq!(v.uv, Vector2(0.0, 1.0));
+        assert_eq!(v.normal, Vector3(0.0, 0.0, -1.0));
+        // Z is flipped and the order of the vertices reversed
+        let coords : Vec<Vector3<f64>> = quad.verts.iter().map(
+            |v| v.coords).collect();
+        assert_eq!(coords[.. 3].to_vec(), vec!(Vector3(0.0, 0.0, 0.0),
+                                               Vector3(1.0, 1.0, 0.0),
+                                               Vector3(1.0, 0.0, 0.0)));
+        assert_eq!(coords[4], Vector3(0.0, 1.0, -1.0));
+
+        let skin = &shapes[1];
+        assert_eq!(skin.verts.len(), 3);
+        assert!(skin.material.texture.is_some());
+        assert_eq!(skin.verts[0].color, RGB(0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn obj_error_test() {
+        let error = |src| match parse_obj("bad.obj", src,
+                                          |_| Ok(HashMap::new())) {
+            Err(ObjError::Syntax { line, message, .. }) => (line, message),
+            r => panic!("expected a syntax error, got {:?}",
+                        r.map(|s| s.len())),
+        };
+        assert_eq!(error("v 0 0 0\nv 1 x 0"),
+                   (2, "expected a number, got \"x\"".to_string()));
+        assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 2"),
+                   (3, "a face needs at least 3 vertices".to_string()));
+        assert_eq!(error("v 0 0 0\n\nf 1 1 4"),
+                   (3, "no vertex number 4".to_string()));
+        assert_eq!(error("v 0 0 0\nf 1/1 1 1"),
+                   (2, "no texture vertex number 1".to_string()));
+        assert_eq!(error("usemtl stone"),
+                   (1, "unknown material \"stone\"".to_string()));
+
+        match parse_mtl("bad.mtl", "Kd 1 1 1", |_| unreachable!()) {
+            Err(ObjError::Syntax { line: 1, .. }) => {},
+            r => panic!("expected a syntax error, got {:?}", r),
+        }
+    }
+}
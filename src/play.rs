@@ -1,21 +1,135 @@
 use tickable::Input;
+use input::Repeat;
 use tickable::Tickable;
 use screen::Screen;
 use state::ProgramState;
+use bindings::Bindings;
 use menu::MenuScreen;
 use geometry::*;
+use texture::{Atlas, Texture};
+use animation::{Animator, Clip, Interpolation, Keyframe, Playback,
+                SpriteFrame};
+use postprocess::Effect;
 use std::mem;
-use labyrinth::Point;
+use std::rc::Rc;
+use std::time::Duration;
+use labyrinth::{Movement, Point};
+use direction::{Direction, DIR_UP};
+use game::SPEED;
+
+/// A square of the size of a cell, centered at the origin.
+fn square(uv: (Vector2<f64>, Vector2<f64>), color: RGB, material: Material)
+    -> Shape {
+        Shape::quad(Vector3(-0.5, -0.5, 0.0), Vector3(0.5, 0.5, 0.0),
+                    uv, color, material)
+    }
+
+/// How long the labyrinth takes to fade out once the exit is reached, in
+/// seconds.
+const ENDING: f64 = 3.0;
+
+/// A copy of a mesh with its colours, emission included, darkened by `k`,
+/// from 0 (black) to 1 (as it is).
+fn dimmed(mesh: &Shape, k: f64) -> Shape {
+    let dim = |c: RGB| RGB((c.0 as f64 * k) as u8, (c.1 as f64 * k) as u8,
+                           (c.2 as f64 * k) as u8);
+    let mut mesh = mesh.clone();
+    for v in mesh.verts.iter_mut() {
+        v.color = dim(v.color);
+    }
+    mesh.material.emission = dim(mesh.material.emission);
+    mesh
+}
+
+/// The meshes shared by all the cells.
+#[derive(Debug)]
+struct Sprites {
+    wall: Rc<Shape>,
+    floor: Rc<Shape>,
+    flame: Rc<Shape>,
+    pc: Atlas,
+}
+
+impl Sprites {
+    fn load() -> Sprites {
+        let whole = (Vector2(0.0, 0.0), Vector2(1.0, 1.0));
+        let white = RGB(0xff, 0xff, 0xff);
+        let tile = |name, material| Rc::new(square(whole, white, Material {
+            texture: Some(Texture::asset(name)),
+            ..material
+        }));
+        Sprites {
+            // A wall hiding the floor or the player is drawn as an outline
+            wall: tile("wall", Material {
+                outline: Some(Outline {
+                    color: RGB(0x60, 0xc0, 0xff),
+                    width: 1.5,
+                    glow: 0.8,
+                }),
+                ..Default::default()
+            }),
+            floor: tile("floor", Material {
+                revealed: true,
+                ..Default::default()
+            }),
+            // Flames glow on their own and the wall shows through them
+            flame: Rc::new(square(whole, RGB(0xff, 0xd0, 0x60), Material {
+                alpha: 0.8,
+                emission: RGB(0xff, 0xd0, 0x60),
+                ..Default::default()
+            })),
+            pc: Atlas::new(Texture::asset("pc"), 5),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum PcAnimation {
+    Idle,
+    /// The walk cycle, a step of it for every cell.
+    Walking,
+}
 
 #[derive(Debug)]
 pub struct PlayScreen {
     state: ProgramState,
+    sprites: Sprites,
+    pc: Animator<PcAnimation, SpriteFrame>,
+    /// The time since the exit was reached, while the labyrinth fades out.
+    ending: Option<f64>,
+    /// The effects played from the moment the exit is reached.
+    outro: Vec<Clip<Effect>>,
 }
 
 impl PlayScreen {
     pub fn new(state: ProgramState) -> PlayScreen {
         PlayScreen {
             state: state,
+            sprites: Sprites::load(),
+            pc: Animator::new(PcAnimation::Idle, 0.0)
+                .with_clip(PcAnimation::Idle,
+                           Clip::frames(&[0], 1.0, Playback::Loop))
+                .with_clip(PcAnimation::Walking,
+                           Clip::frames(&[1, 2, 3, 4, 0], 0.2 / SPEED,
+                                        Playback::Loop)),
+            ending: None,
+            // The thread glows brighter and brighter, then everything
+            // fades to black
+            outro: vec!(
+                Clip::new(vec!(
+                    Keyframe { time: 0.0, pose: Effect::Bloom {
+                        threshold: 0.6, radius: 6, strength: 0.0 } },
+                    Keyframe { time: ENDING, pose: Effect::Bloom {
+                        threshold: 0.6, radius: 6, strength: 2.0 } }),
+                    ENDING, Playback::Once, Interpolation::Linear),
+                Clip::new(vec!(
+                    Keyframe { time: 0.0, pose: Effect::Fade {
+                        color: RGB(0, 0, 0), amount: 0.0 } },
+                    Keyframe { time: ENDING * 0.6, pose: Effect::Fade {
+                        color: RGB(0, 0, 0), amount: 0.0 } },
+                    Keyframe { time: ENDING, pose: Effect::Fade {
+                        color: RGB(0, 0, 0), amount: 1.0 } }),
+                    ENDING, Playback::Once, Interpolation::Linear)),
         }
     }
 }
@@ -23,67 +137,132 @@ impl PlayScreen {
 impl Worldly for PlayScreen {
     fn scene(&self) -> World {
         let mut shapes : Vec<Shape>        = vec!();
-        let mut light  : Vec<Vector3<f64>> = vec!();
+        let mut nodes  : Vec<Node>         = vec!();
+        let mut light  : Vec<Light>        = vec!();
+        let white = RGB(0xff, 0xff, 0xff);
+        let whole = (Vector2(0.0, 0.0), Vector2(1.0, 1.0));
 
         if let Some(ref game) = self.state.game {
-            light.push(Vector3(game.player.x as i32, game.player.y as i32,
-                              1).into_inner());
-
             let w = game.field.0.width() as i32;
             let h = game.field.0.height() as i32;
+            // From 0 until the exit is reached to 1 at the end
+            let ending = self.ending.map_or(0.0, |t| (t / ENDING).min(1.0));
+            let sprite = |mesh: &Rc<Shape>| if ending > 0.0 {
+                Rc::new(dimmed(mesh, 1.0 - ending))
+            } else {
+                mesh.clone()
+            };
+            let (wall, floor, flame) = (sprite(&self.sprites.wall),
+                                        sprite(&self.sprites.floor),
+                                        sprite(&self.sprites.flame));
+
+            // The center of a cell, shifted by a part of the cell size.
+            let cell_point = |p: Point, dx: f64, dy: f64, z: f64| Vector3(
+                (p.y as f64 + 0.5 + dx) * 640.0 / w as f64,
+                (p.x as f64 + 0.5 + dy) * 480.0 / h as f64,
+                z);
+            // Cells are a unit apart inside the labyrinth, which is
+            // stretched over the screen.
+            let mut maze = Node::group(Transform::default()
+                .scale(Vector3(640.0 / w as f64, 480.0 / h as f64, 1.0))
+                .translate(Vector3(0.0, 0.0, 2.0)));
+            let cell = |p: Point, dx: f64, dy: f64, z: f64| Transform::default()
+                .translate(Vector3(p.y as f64 + 0.5 + dx,
+                                   p.x as f64 + 0.5 + dy, z));
+            // The player may be between two cells
+            let (row, column) = game.position();
+            let player = Vector3(column + 0.5, row + 0.5, 0.0);
+
+            light.push(Light {
+                position: Vector3(player.0 * 640.0 / w as f64,
+                                  player.1 * 480.0 / h as f64, 1.0),
+                color: RGB(0x80, 0x80, 0x80),
+                radius: 3.0 * 640.0 / w as f64,
+            });
+
+            for t in game.torches.iter() {
+                let Direction(lr, ud) = t.facing;
+                let (dx, dy) = (lr as i32 as f64 * 0.5,
+                                ud as i32 as f64 * 0.5);
+                maze.children.push(Node::instance(
+                        flame.clone(),
+                        Transform {
+                            scale: Vector3(0.2, 0.2, 1.0),
+                            ..cell(t.wall, dx * 0.8, dy * 0.8, -0.1)
+                        }));
+                light.push(Light {
+                    position: cell_point(t.wall, dx, dy, 1.0),
+                    color: RGB(0xff, 0xc0, 0x80),
+                    radius: 0.75 * self.state.torch_spacing as f64 *
+                        640.0 / w as f64,
+                });
+            }
+
+            // The thread lies on the floor, and brightens up at the exit
+            let point = |p: &Point| Vector3(p.y as f64 + 0.5, p.x as f64 + 0.5,
+                                            0.0);
+            let mut thread : Vec<Vector3<f64>> = game.thread().iter().map(
+                &point).collect();
+            // It follows the player, or is wound up when the player goes back
+            if let Some(step) = game.step {
+                let n = thread.len();
+                if n > 1 && thread[n - 2] == point(&step.to) {
+                    thread[n - 1] = player;
+                } else {
+                    thread.push(player);
+                }
+            }
+            let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) *
+                                      ending) as u8;
+            maze.children.push(Node::instance(
+                    Rc::new(Shape::polyline(
+                            &thread, 0.15, RGB(0xc0, 0x20, 0x20), Material {
+                                emission: RGB(mix(0x30, 0xff), mix(0x08, 0xe0),
+                                              mix(0x08, 0xa0)),
+                                ..Default::default()
+                            })),
+                    Transform::default().translate(Vector3(0.0, 0.0, -0.02))));
+
+            // The frame of the sprite changes, so it isn't shared
+            maze.children.push(Node::instance(
+                    Rc::new(square(
+                            self.sprites.pc.frame(self.pc.pose().0), white,
+                            Material {
+                                revealed: true,
+                                ..Material::textured(
+                                    self.sprites.pc.texture.clone())
+                            })),
+                    Transform {
+                        // The sprite faces upwards
+                        rotation: Quaternion::from_axis_angle(
+                            Vector3(0.0, 0.0, 1.0),
+                            game.facing - DIR_UP.angle()),
+                        ..Transform::default().translate(
+                            player + Vector3(0.0, 0.0, -0.05))
+                    }));
 
             for x in 0 .. h {
                 for y in 0 .. w {
-                    let coord_to_vertex = |r: RGB, c: &Vector3<i32>| Vertex {
-                                       coords: c.into_inner(),
-                                       color: r
-                    };
-                    if game.field.0[Point{x: x as usize, y: y as usize}] {
-                        let r = RGB(0x61, 0x40, 0x20);
-                        shapes.push(Shape {
-                            verts: vec!(
-                             Vector3(( y  ) * 640 / w, (x+1) * 480 / h, 2),
-                             Vector3(( y+1) * 640 / w, (x+1) * 480 / h, 2),
-                             Vector3(( y  ) * 640 / w, (x  ) * 480 / h, 2),
-                             Vector3(( y+1) * 640 / w, (x  ) * 480 / h, 2)).
-                                     iter().map(|c| coord_to_vertex(r, c)).
-                                     collect(),
-                            primitive: Primitive::TriangleStrip,
-                        });
-                    } else {
-                        let r = RGB(0x40, 0x20, 0x61);
-                        shapes.push(Shape {
-                            verts: vec!(
-                             Vector3(( y  ) * 640 / w, (x+1) * 480 / h, 2),
-                             Vector3(( y+1) * 640 / w, (x+1) * 480 / h, 2),
-                             Vector3(( y  ) * 640 / w, (x  ) * 480 / h, 2),
-                             Vector3(( y+1) * 640 / w, (x  ) * 480 / h, 2)).
-                                     iter().map(|c| coord_to_vertex(r, c)).
-                                     collect(),
-                            primitive: Primitive::TriangleStrip,
-                        });
-                    }
+                    let p = Point{x: x as usize, y: y as usize};
+                    let tile = if game.field.0[p] { &wall } else { &floor };
+                    maze.children.push(Node::instance(
+                            tile.clone(), cell(p, 0.0, 0.0, 0.0)));
                 }
             }
+            nodes.push(maze);
         }
-        let coord_to_vertex = |r: RGB, c: &Vector3<f64>| Vertex {
-                           coords: *c,
-                           color: r
-        };
-        shapes.push(Shape {
-                   verts: vec!(
-                       Vector3(0.1  , 479.0, 3.1),
-                       Vector3(0.1  , 0.1  , 3.1),
-                       Vector3(639.0, 479.0, 3.1),
-                       Vector3(639.0, 0.1  , 3.1)).iter().map(
-                           |c| coord_to_vertex(RGB(0x15, 0x15, 0x15), c)).
-                           collect(),
-                   primitive: Primitive::TriangleStrip,
-        });
+        shapes.push(Shape::quad(
+                Vector3(0.1, 0.1, 3.1), Vector3(639.0, 479.0, 3.1),
+                whole, RGB(0x15, 0x15, 0x15), Material::default()));
 
         World {
             shapes   : shapes,
-            lighting : light
+            nodes    : nodes,
+            lighting : light,
+            effects  : match self.ending {
+                Some(t) => self.outro.iter().map(|c| c.sample(t)).collect(),
+                None => vec!(),
+            }
         }
     }
 }
@@ -91,26 +270,95 @@ impl Worldly for PlayScreen {
 impl Screen for PlayScreen {
     fn tick(&mut self, input: Option<Input>) -> Option<Box<Screen>> {
         match input {
+            // The game is over, the labyrinth is fading out
+            _ if self.ending.is_some() => None,
             Some(Input::Menu) =>
                 Some(Box::new(MenuScreen::new(mem::replace(
                                 &mut self.state,
                                 ProgramState::new())))),
             _ => {
-                let mut finished = false;
                 if let Some(ref mut game) = self.state.game {
                     game.tick(input);
-                    finished = game.is_finished();
-                }
-                if finished {
-                    self.state.game = None;
-                    Some(Box::new(MenuScreen::new(mem::replace(
-                                    &mut self.state,
-                                    ProgramState::new()))))
-                } else {
-                    None
                 }
+                None
+            }
+        }
+    }
+
+    /// Directions are held rather than repeated, so that the player walks
+    /// on as long as a key is down.
+    fn repeat(&self) -> Option<Repeat> {
+        None
+    }
+
+    /// The player goes the way of the latest direction held; moving eight
+    /// ways, the earlier ones may add to it, as up and right make up-right.
+    fn hold(&mut self, inputs: &[Input]) {
+        if let Some(ref mut game) = self.state.game {
+            let held : Vec<Direction> = inputs.iter().filter_map(
+                |i| match *i {
+                    Input::Direction(d) => Some(d),
+                    _ => None,
+                }).collect();
+            game.held = match held.last() {
+                _ if self.ending.is_some() => None,
+                Some(&d) if game.movement != Movement::FourWay =>
+                    Some(held.iter().rev().fold(d, |acc, &e| acc.and(e))),
+                last => last.cloned(),
+            };
+        }
+    }
+
+    fn bindings(&self) -> &Bindings {
+        &self.state.bindings
+    }
+
+    /// Any key stops the walk to a clicked cell, bound or not.
+    fn key(&mut self, _: &str) -> bool {
+        if let Some(ref mut game) = self.state.game {
+            game.route.clear();
+        }
+        false
+    }
+
+    /// Walk to the cell which was clicked, as it's laid out in `scene`.
+    fn click(&mut self, point: Vector3<f64>) {
+        if self.ending.is_some() {
+            return;
+        }
+        if let Some(ref mut game) = self.state.game {
+            let (w, h) = (game.field.0.width(), game.field.0.height());
+            let (column, row) = (point.0 * w as f64 / 640.0,
+                                 point.1 * h as f64 / 480.0);
+            if column >= 0.0 && row >= 0.0 {
+                game.walk_to(Point { x: row as usize, y: column as usize });
+            }
+        }
+    }
+
+    fn advance(&mut self, dt: Duration) -> Option<Box<Screen>> {
+        let dt = dt.as_secs() as f64 + dt.subsec_nanos() as f64 * 1e-9;
+        self.ending = self.ending.map(|t| t + dt);
+        if let Some(ref mut game) = self.state.game {
+            game.advance(dt);
+            self.pc.play(if game.is_moving() {
+                PcAnimation::Walking
+            } else {
+                PcAnimation::Idle
+            });
+            if game.is_finished() && self.ending.is_none() {
+                self.ending = Some(0.0);
             }
         }
+        self.pc.advance(dt);
+        if self.ending.map_or(false, |t| t >= ENDING) {
+            self.state.game = None;
+            Some(Box::new(MenuScreen::new(mem::replace(
+                            &mut self.state,
+                            ProgramState::new()))))
+        } else {
+            None
+        }
     }
 }
 
@@ -0,0 +1,196 @@
+//! Effects applied to the whole picture once it's rendered
+//!
+//! The effects of a `World` are applied one after another to the traced
+//! colours, in linear light, before they are turned into pixels. Screens can
+//! change them from frame to frame, or animate them with clips, since effects
+//! of the same kind blend into each other.
+
+use animation::Pose;
+use framebuffer::FrameBuffer;
+use geometry::{RGB, RGBf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Mixes the picture with a colour, `amount` going from 0 (the picture
+    /// as it is) to 1 (only the colour).
+    Fade { color: RGB, amount: f64 },
+    /// Scales the colours by `brightness`, then moves them away from middle
+    /// grey by `contrast`; 1 leaves them as they are.
+    Grade { brightness: f64, contrast: f64 },
+    /// Makes the parts brighter than `threshold`, a share of white, glow over
+    /// `radius` pixels around them, `strength` times as bright.
+    Bloom { threshold: f64, radius: usize, strength: f64 },
+    /// Darkens the picture towards the corners, from `radius` times the
+    /// distance from the middle to a corner, by `strength` at the corners.
+    Vignette { radius: f64, strength: f64 },
+}
+
+impl Effect {
+    pub fn apply(&self, image: &mut FrameBuffer<RGBf>) {
+        let (w, h) = (image.width(), image.height());
+        match *self {
+            Effect::Fade { color, amount } => {
+                let color = color.to_linear();
+                each(image, |_, c| c * (1.0 - amount) + color * amount);
+            },
+            Effect::Grade { brightness, contrast } => {
+                let grey = RGB(0x80, 0x80, 0x80).to_linear().0;
+                let grade = |x: f64| ((x * brightness - grey) * contrast +
+                                      grey).max(0.0);
+                each(image, |_, c| RGBf(grade(c.0), grade(c.1), grade(c.2)));
+            },
+            Effect::Bloom { threshold, radius, strength } => {
+                let white = RGB(0xff, 0xff, 0xff).to_linear().0;
+                let over = |x: f64| (x - threshold * white).max(0.0);
+                let mut bright = image.clone();
+                each(&mut bright, |_, c| RGBf(over(c.0), over(c.1), over(c.2)));
+                let bright = blur(&bright, radius);
+                each(image, |at, c| c + bright[at] * strength);
+            },
+            Effect::Vignette { radius, strength } => {
+                each(image, |(x, y), c| {
+                    let dx = (x as f64 + 0.5) / w as f64 - 0.5;
+                    let dy = (y as f64 + 0.5) / h as f64 - 0.5;
+                    // From 0 in the middle to 1 in the corners
+                    let d = ((dx * dx + dy * dy) * 2.0).sqrt();
+                    let s = ((d - radius) / (1.0 - radius)).max(0.0).min(1.0);
+                    c * (1.0 - strength * s * s * (3.0 - 2.0 * s))
+                });
+            },
+        }
+    }
+}
+
+impl Pose for Effect {
+    /// Effects of the same kind blend their parameters; different ones can't
+    /// be mixed, so the closest one is taken.
+    fn blend(&self, other: &Effect, t: f64) -> Effect {
+        use self::Effect::*;
+        let mix = |a: f64, b: f64| a + (b - a) * t;
+        let mix8 = |a: u8, b: u8| mix(a as f64, b as f64).round() as u8;
+        match (*self, *other) {
+            (Fade { color: c0, amount: a0 }, Fade { color: c1, amount: a1 }) =>
+                Fade {
+                    color: RGB(mix8(c0.0, c1.0), mix8(c0.1, c1.1),
+                               mix8(c0.2, c1.2)),
+                    amount: mix(a0, a1),
+                },
+            (Grade { brightness: b0, contrast: c0 },
+             Grade { brightness: b1, contrast: c1 }) =>
+                Grade { brightness: mix(b0, b1), contrast: mix(c0, c1) },
+            (Bloom { threshold: t0, radius: r0, strength: s0 },
+             Bloom { threshold: t1, radius: r1, strength: s1 }) =>
+                Bloom {
+                    threshold: mix(t0, t1),
+                    radius: mix(r0 as f64, r1 as f64).round() as usize,
+                    strength: mix(s0, s1),
+                },
+            (Vignette { radius: r0, strength: s0 },
+             Vignette { radius: r1, strength: s1 }) =>
+                Vignette { radius: mix(r0, r1), strength: mix(s0, s1) },
+            _ => if t < 0.5 { *self } else { *other },
+        }
+    }
+}
+
+/// Replace every colour of a picture, given its column and row.
+fn each<F>(image: &mut FrameBuffer<RGBf>, f: F)
+    where F: Fn((usize, usize), RGBf) -> RGBf {
+        for y in 0 .. image.height() {
+            for x in 0 .. image.width() {
+                image[(x, y)] = f((x, y), image[(x, y)]);
+            }
+        }
+    }
+
+/// A picture blurred over `radius` pixels around each pixel, the nearer
+/// pixels weighing more.
+pub fn blur(image: &FrameBuffer<RGBf>, radius: usize) -> FrameBuffer<RGBf> {
+    let (w, h) = (image.width(), image.height());
+    let r = radius as isize;
+    let weights : Vec<f64> = (-r .. r + 1).map(
+        |d| (r + 1 - d.abs()) as f64).collect();
+    let total : f64 = weights.iter().sum();
+    let pass = |src: &FrameBuffer<RGBf>, (dx, dy): (isize, isize)| {
+        let mut out = FrameBuffer::new(w, h, RGBf(0.0, 0.0, 0.0));
+        for y in 0 .. h {
+            for x in 0 .. w {
+                let mut c = RGBf(0.0, 0.0, 0.0);
+                for (d, &k) in (-r .. r + 1).zip(weights.iter()) {
+                    let (sx, sy) = (x as isize + d * dx, y as isize + d * dy);
+                    if sx >= 0 && sy >= 0 && (sx as usize) < w &&
+                        (sy as usize) < h {
+                        c = c + src[(sx as usize, sy as usize)] * k;
+                    }
+                }
+                out[(x, y)] = c * (1.0 / total);
+            }
+        }
+        out
+    };
+    pass(&pass(image, (1, 0)), (0, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::Pose;
+    use framebuffer::FrameBuffer;
+    use geometry::{RGB, RGBf};
+    use super::*;
+
+    fn grey(x: u8) -> RGBf {
+        RGB(x, x, x).to_linear()
+    }
+
+    fn close(a: RGBf, b: RGBf) -> bool {
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9 &&
+            (a.2 - b.2).abs() < 1e-9
+    }
+
+    #[test]
+    fn effects_test() {
+        let picture = FrameBuffer::new(9, 9, grey(0x40));
+        let apply = |e: Effect| {
+            let mut image = picture.clone();
+            e.apply(&mut image);
+            image
+        };
+
+        let faded = apply(Effect::Fade { color: RGB(0, 0, 0), amount: 0.25 });
+        assert!(close(faded[(4, 4)], grey(0x40) * 0.75));
+
+        // Middle grey stays as it is, darker colours get darker
+        let mut image = picture.clone();
+        image[(0, 0)] = grey(0x80);
+        Effect::Grade { brightness: 1.0, contrast: 2.0 }.apply(&mut image);
+        assert!(close(image[(0, 0)], grey(0x80)));
+        assert!(image[(4, 4)].0 < grey(0x40).0);
+
+        let vignette = apply(Effect::Vignette { radius: 0.5, strength: 1.0 });
+        assert_eq!(vignette[(4, 4)], grey(0x40));
+        assert!(vignette[(0, 0)].0 < vignette[(0, 4)].0);
+        assert!(vignette[(0, 4)].0 < grey(0x40).0);
+
+        // Only what is bright enough glows, and around itself
+        let mut image = picture.clone();
+        image[(4, 4)] = grey(0xff);
+        Effect::Bloom { threshold: 0.5, radius: 2, strength: 1.0 }.apply(
+            &mut image);
+        assert!(image[(4, 4)].0 > grey(0xff).0);
+        assert!(image[(4, 6)].0 > grey(0x40).0);
+        assert_eq!(image[(4, 7)], grey(0x40));
+        assert_eq!(image[(0, 0)], grey(0x40));
+    }
+
+    #[test]
+    fn blend_test() {
+        let fade = |amount| Effect::Fade {
+            color: RGB(0, 0, 0),
+            amount: amount,
+        };
+        assert_eq!(fade(0.0).blend(&fade(1.0), 0.25), fade(0.25));
+        let grade = Effect::Grade { brightness: 2.0, contrast: 1.0 };
+        assert_eq!(fade(0.0).blend(&grade, 0.25), fade(0.0));
+        assert_eq!(fade(0.0).blend(&grade, 0.75), grade);
+    }
+}
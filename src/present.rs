@@ -0,0 +1,77 @@
+use framebuffer::{FrameBuffer, RowOrder};
+use geometry::RGB;
+use glium::{Rect, Surface};
+use glium::backend::Facade;
+use glium::texture::{ClientFormat, MipmapsOption, RawImage2d, Texture2d,
+                     TextureCreationError, UncompressedFloatFormat};
+use glium::uniforms::MagnifySamplerFilter;
+use std::borrow::Cow;
+
+/// Puts rendered frames on the screen
+///
+/// Owns a single texture which every frame is written into, along with the
+/// buffer the pixels are packed into before they are sent to the GPU, so
+/// nothing is allocated per frame unless the size changes.
+pub struct Presenter {
+    texture: Texture2d,
+    bytes: Vec<u8>,
+}
+
+impl Presenter {
+    pub fn new<F: Facade>(display: &F, width: u32, height: u32)
+        -> Result<Presenter, TextureCreationError> {
+            Ok(Presenter {
+                texture: try!(Presenter::texture(display, width, height)),
+                bytes: Vec::with_capacity(width as usize * height as usize * 3),
+            })
+        }
+
+    fn texture<F: Facade>(display: &F, width: u32, height: u32)
+        -> Result<Texture2d, TextureCreationError> {
+            Texture2d::empty_with_format(display,
+                                         UncompressedFloatFormat::U8U8U8,
+                                         MipmapsOption::NoMipmap,
+                                         width.max(1), height.max(1))
+        }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture.get_width(), self.texture.get_height().unwrap_or(1))
+    }
+
+/// Make the texture as large as the given size, if it isn't already.
+    pub fn resize<F: Facade>(&mut self, display: &F, width: u32, height: u32)
+        -> Result<(), TextureCreationError> {
+            if self.size() != (width.max(1), height.max(1)) {
+                self.texture = try!(Presenter::texture(display, width, height));
+            }
+            Ok(())
+        }
+
+/// Copy a frame into the texture
+///
+/// The frame has to be as large as the texture; its first row is the top one.
+    pub fn upload(&mut self, pixels: &FrameBuffer<RGB>) {
+        let (width, height) = self.size();
+        assert_eq!((pixels.width(), pixels.height()),
+                   (width as usize, height as usize));
+        self.bytes.clear();
+        // OpenGL textures start with the bottom row
+        pixels.write_rgb8(&mut self.bytes, RowOrder::BottomUp);
+        self.texture.write(Rect {
+            left: 0,
+            bottom: 0,
+            width: width,
+            height: height,
+        }, RawImage2d {
+            data: Cow::Borrowed(&self.bytes[..]),
+            width: width,
+            height: height,
+            format: ClientFormat::U8U8U8,
+        });
+    }
+
+/// Stretch the last uploaded frame over a surface.
+    pub fn draw<S: Surface>(&self, target: &S) {
+        self.texture.as_surface().fill(target, MagnifySamplerFilter::Linear);
+    }
+}
@@ -1,35 +1,239 @@
 use geometry::*;
+use camera::{Camera, Frustum};
+use framebuffer::FrameBuffer;
+use postprocess::{blur, Effect};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::convert::Into;
 use std::f64;
+use std::fmt;
 use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The share of a surface colour that is visible without any light.
+const AMBIENT: f64 = 0.25;
+
+/// The distance along a ray at which obstacles are not yet counted, so that a
+/// surface doesn't cast a shadow onto itself, and a transparent one isn't
+/// seen twice.
+const SHADOW_BIAS: f64 = 1e-6;
+
+/// The most surfaces a ray goes through before it stops, when they're
+/// transparent.
+const MAX_LAYERS: usize = 8;
+
+/// The share of light below which a ray stops going through surfaces.
+const OPAQUE: f64 = 1e-3;
+
+/// How far the glow of outlines spreads, in pixels.
+const GLOW_RADIUS: usize = 4;
 
 pub struct Canvas {
     width: u16,
     height: u16,
-    pixels: Vec<Vec<RGB>>,
-    zbuffer: Vec<Vec<f64>>,
+    pixels: FrameBuffer<RGB>,
+    /// The traced colours, in linear light.
+    linear: FrameBuffer<RGBf>,
+    /// The light of the outlines, which glows around them.
+    glow: FrameBuffer<RGBf>,
+    /// Whether there was any glow when the pixels were last composed.
+    glowing: bool,
+    /// The effects the pixels were last composed with.
+    effects: Vec<Effect>,
+    zbuffer: FrameBuffer<f64>,
+    antialiasing: Antialiasing,
+    /// What was rendered last, to find out which pixels need to be traced
+    /// again.
+    previous: Option<Frame>,
+    stats: RenderStats,
+}
+
+/// How much work the last frame took
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Polygons in the world.
+    pub submitted: usize,
+    /// Polygons left out, being out of view and out of the reach of the
+    /// lights in view.
+    pub culled: usize,
+    /// Polygons out of view which are kept for the shadows they may cast.
+    pub shadow_casters: usize,
+    /// Polygons in view which face away from the camera and aren't shown.
+    pub backfaces: usize,
+    /// Polygons which can be seen.
+    pub drawn: usize,
+    /// Pixels traced, rather than kept from the previous frame.
+    pub pixels: usize,
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} polygons: {} drawn, {} back faces, {} shadow casters, \
+                   {} culled; {} pixels traced", self.submitted, self.drawn,
+               self.backfaces, self.shadow_casters, self.culled, self.pixels)
+    }
+}
+
+/// A rendered scene, as much of it as it takes to tell what has changed.
+struct Frame {
+    space: Box<Space>,
+    /// The fingerprints and the bounds of the shapes.
+    shapes: Vec<(u64, Option<Aabb>)>,
+    lights: Vec<Light>,
+    camera: Camera,
+    antialiasing: Antialiasing,
+    /// The bounds of the faces drawn as outlines.
+    xray: Vec<Aabb>,
+}
+
+/// A hash of everything about a shape which affects how it looks.
+fn fingerprint(shape: &Shape) -> u64 {
+    let mut h = DefaultHasher::new();
+    for v in shape.verts.iter() {
+        for &x in [v.coords.0, v.coords.1, v.coords.2, v.uv.0, v.uv.1,
+                   v.normal.0, v.normal.1, v.normal.2].iter() {
+            x.to_bits().hash(&mut h);
+        }
+        v.color.hash(&mut h);
+    }
+    shape.primitive.hash(&mut h);
+    shape.material.texture.as_ref().map(
+        |t| &**t as *const _ as usize).hash(&mut h);
+    (shape.material.sampling as u8).hash(&mut h);
+    shape.material.cull_backfaces.hash(&mut h);
+    shape.material.alpha.to_bits().hash(&mut h);
+    shape.material.emission.hash(&mut h);
+    shape.material.outline.map(|o| (o.color, o.width.to_bits(),
+                                     o.glow.to_bits())).hash(&mut h);
+    shape.material.revealed.hash(&mut h);
+    h.finish()
+}
+
+/// The way rays are spread over a pixel
+///
+/// The colour of a pixel is the average of the colours seen by all its rays,
+/// so the edges of polygons look smooth when there's more than one.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Antialiasing {
+    /// An n by n grid of evenly spaced rays.
+    Grid(u8),
+    /// An n by n grid of cells, each with a ray through a random point.
+    Jittered(u8),
+}
+
+impl Antialiasing {
+    /// The points of a pixel to shoot rays through, from (0, 0) at its top
+    /// left corner to (1, 1) at the bottom right one.
+    fn samples(&self, random: &mut u32) -> Vec<(f64, f64)> {
+        let (n, jitter) = match *self {
+            Antialiasing::Grid(n) => (n.max(1), false),
+            Antialiasing::Jittered(n) => (n.max(1), true),
+        };
+        let mut next = || if jitter {
+            // xorshift32
+            *random ^= *random << 13;
+            *random ^= *random >> 17;
+            *random ^= *random << 5;
+            *random as f64 / (u32::max_value() as f64 + 1.0)
+        } else {
+            0.5
+        };
+        let mut res = Vec::with_capacity(n as usize * n as usize);
+        for i in 0 .. n {
+            for j in 0 .. n {
+                let (dx, dy) = (next(), next());
+                res.push(((i as f64 + dx) / n as f64,
+                          (j as f64 + dy) / n as f64));
+            }
+        }
+        res
+    }
+}
+
+impl FromStr for Antialiasing {
+    type Err = String;
+
+    /// Either `grid:N` or `jitter:N`, for N by N rays.
+    fn from_str(s: &str) -> Result<Antialiasing, String> {
+        let parts : Vec<&str> = s.splitn(2, ':').collect();
+        let n = try!(parts.get(1).and_then(|n| n.parse().ok()).ok_or(
+                format!("expected the number of rays in {}", s)));
+        match parts[0] {
+            "grid" => Ok(Antialiasing::Grid(n)),
+            "jitter" => Ok(Antialiasing::Jittered(n)),
+            _ => Err(format!("unknown antialiasing {}", parts[0])),
+        }
+    }
 }
 
-struct Voxel<'b> {
-    polygons: Vec<&'b Polygon>
+/// A polygon together with the material of the shape it belongs to.
+struct Face {
+    polygon: Polygon,
+    material: Material,
+    /// Whether the camera can see the polygon; the hidden ones only cast
+    /// shadows.
+    visible: bool,
+    /// Which edges are on the border of the shape, the edge `i` being the
+    /// one opposite to the vertex `i`.
+    borders: [bool; 3],
+    /// Whether the face hides a revealed one and is drawn as an outline.
+    xray: bool,
 }
 
-impl<'b> Default for Voxel<'b> {
-    fn default() -> Voxel<'b> {
+struct Voxel {
+    /// Indices of the faces of `Space`.
+    polygons: Vec<usize>
+}
+
+impl Default for Voxel {
+    fn default() -> Voxel {
         Voxel {
             polygons: vec!()
         }
     }
 }
 
-struct Space<'b> {
-    grid: [[[Voxel<'b>; 12]; 12]; 12]
+/// The number of voxels along each axis of `Space`.
+const GRID: usize = 12;
+
+struct Space {
+    faces: Vec<Face>,
+    grid: [[[Voxel; GRID]; GRID]; GRID],
+    /// The corners of the box which contains all the polygons.
+    min: Vector3<f64>,
+    max: Vector3<f64>,
 }
 
-impl<'b> Space<'b> {
-    fn new(poly: &'b Vec<Polygon>) -> Space<'b> {
-        let mut g : [[[Voxel; 12]; 12]; 12] = Default::default();
-        for p in poly {
+impl Space {
+    fn new(faces: Vec<Face>) -> Space {
+        let mut min = Vector3(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = min * -1.0;
+        for f in faces.iter() {
+            for v in [f.polygon.0, f.polygon.1, f.polygon.2].iter() {
+                for &d in [Dimension::X, Dimension::Y, Dimension::Z].iter() {
+                    min[d] = min[d].min(v.coords[d]);
+                    max[d] = max[d].max(v.coords[d]);
+                }
+            }
+        }
+        for &d in [Dimension::X, Dimension::Y, Dimension::Z].iter() {
+            if !(max[d] - min[d] > f64::EPSILON) {
+                // Flat or empty scenes still need voxels of non-zero size.
+                min[d] = if min[d].is_finite() { min[d] } else { 0.0 };
+                max[d] = min[d] + 1.0;
+            }
+        }
+
+        let mut space = Space {
+            faces: vec!(),
+            grid: Default::default(),
+            min: min,
+            max: max,
+        };
+
+        for (i, f) in faces.iter().enumerate() {
+            let p = &f.polygon;
             let verts = [p.0, p.1, p.2];
             let coords : Vec<&Vector3<f64>> = verts.iter().map(
                 |x| &x.coords).collect();
@@ -50,42 +254,55 @@ impl<'b> Space<'b> {
             zs.sort_by(&cmp);
 
             let range = |a: &Vec<f64>, d: Dimension| {
-                match (Space::dimension_idx(a[0], d),
-                       Space::dimension_idx(a[2], d)) {
+                match (space.dimension_idx(a[0], d),
+                       space.dimension_idx(a[2], d)) {
                     (Some(left), Some(right)) => left..(right + 1),
                     _ => 0..0
                 }
             };
 
-            for x in range(&xs, Dimension::X) {
-                for y in range(&ys, Dimension::Y) {
-                    for z in range(&zs, Dimension::Z) {
-                        g[x][y][z].polygons.push(p);
+            let (rx, ry, rz) = (range(&xs, Dimension::X),
+                                range(&ys, Dimension::Y),
+                                range(&zs, Dimension::Z));
+            for x in rx {
+                for y in ry.clone() {
+                    for z in rz.clone() {
+                        space.grid[x][y][z].polygons.push(i);
                     }
                 }
             }
         }
-        Space { grid: g }
+        space.faces = faces;
+        space
     }
 
-    fn dimension_idx(a: f64, d: Dimension) -> Option<usize> {
-        let ar = match d {
-            Dimension::X => (0.0, 640.0),
-            Dimension::Y => (0.0, 480.0),
-            Dimension::Z => (0.0, 10.0),
-        };
-        if a > ar.1 || a < ar.0 {
+    fn voxel_size(&self, d: Dimension) -> f64 {
+        (self.max[d] - self.min[d]) / GRID as f64
+    }
+
+    fn dimension_idx(&self, a: f64, d: Dimension) -> Option<usize> {
+        if a > self.max[d] || a < self.min[d] {
             None
         } else {
-            [((a - ar.0) * 12 as f64 / (ar.1 - ar.0)).floor() as usize, 12 - 1].
-                iter().min().map(|&x| x)
+            [((a - self.min[d]) / self.voxel_size(d)).floor() as usize,
+             GRID - 1].iter().min().map(|&x| x)
         }
     }
 
+    /// Whether a point is inside the voxel, give or take a rounding error.
+    fn voxel_contains(&self, voxel: (usize, usize, usize),
+                      p: Vector3<f64>) -> bool {
+        let (x, y, z) = voxel;
+        [(Dimension::X, x), (Dimension::Y, y), (Dimension::Z, z)].iter().all(
+            |&(d, i)| {
+                let eps = self.voxel_size(d) * 1e-9;
+                let lo = self.min[d] + i as f64 * self.voxel_size(d);
+                p[d] >= lo - eps && p[d] <= lo + self.voxel_size(d) + eps
+            })
+    }
+
     fn ray_boxes(&self, origin: Vector3<f64>, direction: Vector3<f64>) ->
     Vec<(usize, usize, usize)> {
-        let mut point = origin;
-
         let inv_dir = Vector3(
             1.0 / direction[Dimension::X],
             1.0 / direction[Dimension::Y],
@@ -100,26 +317,35 @@ impl<'b> Space<'b> {
         let dims : Vec<Dimension> = [Dimension::X, Dimension::Y, Dimension::Z].
             iter().filter(|&&x| direction[x].abs() > f64::EPSILON).
             map(|&x| x).collect();
-        for &d in dims.iter() {
-            if point[d] < 0.0 {
-                point = point - direction * inv_dir[d] * point[d];
+
+        // Find where the ray enters the box containing the voxels.
+        let mut t_enter : f64 = 0.0;
+        let mut t_exit = f64::INFINITY;
+        for &d in [Dimension::X, Dimension::Y, Dimension::Z].iter() {
+            if direction[d].abs() > f64::EPSILON {
+                let a = (self.min[d] - origin[d]) * inv_dir[d];
+                let b = (self.max[d] - origin[d]) * inv_dir[d];
+                t_enter = t_enter.max(a.min(b));
+                t_exit = t_exit.min(a.max(b));
+            } else if origin[d] < self.min[d] || origin[d] > self.max[d] {
+                return vec!();
             }
         }
+        if t_enter > t_exit || dims.is_empty() {
+            return vec!();
+        }
+        let mut point = origin + direction * t_enter;
 
+        let idx = |p: f64, d: Dimension| self.dimension_idx(p, d).unwrap_or(
+            if p < self.min[d] { 0 } else { GRID - 1 }) as i32;
         let mut coords = Vector3(
-            Space::dimension_idx(point[Dimension::X], Dimension::X).
-                unwrap() as i32,
-            Space::dimension_idx(point[Dimension::Y], Dimension::Y).
-                unwrap() as i32,
-            Space::dimension_idx(point[Dimension::Z], Dimension::Z).
-                unwrap() as i32);
+            idx(point[Dimension::X], Dimension::X),
+            idx(point[Dimension::Y], Dimension::Y),
+            idx(point[Dimension::Z], Dimension::Z));
 
         let nearest_bound = |c: &Vector3<i32>, d: Dimension| {
-            (c[d] + if (dir_sign[d] > 0) { 1 } else { 0 }) as f64 * match d {
-                Dimension::X => 640.0,
-                Dimension::Y => 480.0,
-                Dimension::Z =>  10.0
-            } / 12.0
+            self.min[d] + (c[d] + if dir_sign[d] > 0 { 1 } else { 0 }) as f64 *
+                self.voxel_size(d)
         };
 
         let mut t_max = Vector3(
@@ -128,10 +354,6 @@ impl<'b> Space<'b> {
                 (nearest_bound(&coords, Dimension::Z) - point[Dimension::Z]) * inv_dir[Dimension::Z],
             );
 
-        let t_delta = Vector3(640.0 / 12 as f64 * inv_dir[Dimension::X],
-                              480.0 / 12 as f64 * inv_dir[Dimension::Y],
-                               10.0 / 12 as f64 * inv_dir[Dimension::Z]);
-
         let coords_usize = (coords.0 as usize,
                             coords.1 as usize,
                             coords.2 as usize);
@@ -147,7 +369,7 @@ impl<'b> Space<'b> {
                         }});
             let d = dims_vec[0];
             coords[d] = coords[d] + dir_sign[d];
-            if coords[d] < 0 || coords[d] >= 12 {
+            if coords[d] < 0 || coords[d] >= GRID as i32 {
                 break;
             }
 
@@ -173,17 +395,75 @@ impl Canvas {
         Canvas {
             width: w,
             height: h,
-            pixels: vec!(vec!(RGB(0, 0, 0); w as usize); h as usize),
-            zbuffer: vec!(vec!(0.0; w as usize); h as usize),
+            pixels: FrameBuffer::new(w as usize, h as usize, RGB(0, 0, 0)),
+            linear: FrameBuffer::new(w as usize, h as usize,
+                                     RGBf(0.0, 0.0, 0.0)),
+            glow: FrameBuffer::new(w as usize, h as usize, RGBf(0.0, 0.0, 0.0)),
+            glowing: false,
+            effects: vec!(),
+            zbuffer: FrameBuffer::new(w as usize, h as usize, f64::INFINITY),
+            antialiasing: Antialiasing::Grid(1),
+            previous: None,
+            stats: Default::default(),
         }
     }
 
-    pub fn pixels(&self) -> &Vec<Vec<RGB>> {
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Change the size of the picture, which is then traced again as a whole.
+    pub fn resize(&mut self, w: u16, h: u16) {
+        if (w, h) != (self.width, self.height) {
+            *self = Canvas {
+                antialiasing: self.antialiasing,
+                ..Canvas::new(w, h)
+            };
+        }
+    }
+
+    /// Make the next `render` trace every pixel again.
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn set_antialiasing(&mut self, aa: Antialiasing) {
+        self.antialiasing = aa;
+    }
+
+    pub fn pixels(&self) -> &FrameBuffer<RGB> {
         &self.pixels
     }
 
+    /// The point of the world shown at a point of the last picture rendered,
+    /// in pixels from its top left corner
+    ///
+    /// The point is on the nearest surface seen there, transparent or not;
+    /// `None` if there's nothing there or nothing was rendered yet.
+    pub fn pick(&self, x: f64, y: f64) -> Option<Vector3<f64>> {
+        let frame = match self.previous {
+            Some(ref f) => f,
+            None => return None,
+        };
+        let camera = &frame.camera;
+        let (forward, _, _) = camera.basis();
+        let (origin, dir) = camera.ray(
+            2.0 * x / self.width as f64 - 1.0,
+            1.0 - 2.0 * y / self.height as f64);
+        let speed = dir.dot(forward);
+        let start = camera.depth(origin);
+        Canvas::closest_polygon(origin, dir, &frame.space,
+                                ((camera.near - start) / speed,
+                                 (camera.far - start) / speed)).map(
+            |(_, p, _, _)| p)
+    }
+
     fn raytrace(origin: Vector3<f64>, direction: Vector3<f64>, poly: &Polygon)->
-        Option<(f64, Vector3<f64>)> {
+        Option<(f64, Vector3<f64>, Barycentric)> {
             let v0 : Vector3<f64> = poly.0.coords.into();
             let v1 : Vector3<f64> = poly.1.coords.into();
             let v2 : Vector3<f64> = poly.2.coords.into();
@@ -204,35 +484,51 @@ impl Canvas {
 
             let p = origin + direction * t;
 
-            if [(v1, v0), (v2, v1), (v0, v2)].iter().any(
-                |&(a, b)| poly_normal.dot((a - b) * (p - b)) < 0.0) {
-                    return None;
-                }
+            // Twice the signed areas of the triangles that the point makes
+            // with each edge, opposite to the first, second and third vertex.
+            let areas : Vec<f64> = [(v2, v1), (v0, v2), (v1, v0)].iter().map(
+                |&(a, b)| poly_normal.dot((a - b) * (p - b))).collect();
+
+            if areas.iter().any(|&x| x < 0.0) {
+                return None;
+            }
 
-            Some((t, p))
+            let total = poly_normal.dot(poly_normal);
+            Some((t, p, Barycentric(areas[0] / total,
+                                    areas[1] / total,
+                                    areas[2] / total)))
         }
 
     fn closest_polygon<'b>(origin: Vector3<f64>, direction: Vector3<f64>,
-          space: &Space<'b>) -> Option<(Polygon, Vector3<f64>, f64)> {
+          space: &'b Space, t_range: (f64, f64))
+        -> Option<(&'b Face, Vector3<f64>, Barycentric, f64)> {
               let boxes = space.ray_boxes(origin, direction);
+              let mut min = f64::INFINITY;
+              let mut poly = None;
               for (x, y, z) in boxes {
-                  let polys = space.grid[x][y][z].polygons.clone();
-                  let mut min = f64::INFINITY;
-                  let mut poly = None;
-                  for p in polys {
-                      if let Some((t, c)) = Canvas::raytrace(
-                          origin, direction, p) {
-                              if t < min {
+                  for &i in space.grid[x][y][z].polygons.iter() {
+                      let p = &space.faces[i];
+                      if !p.visible {
+                          continue;
+                      }
+                      if let Some((t, c, b)) = Canvas::raytrace(
+                          origin, direction, &p.polygon) {
+                              if t < min && t >= t_range.0 && t <= t_range.1 {
                                   min = t;
-                                  poly = Some((p, c));
+                                  poly = Some((p, c, b));
                               }
                           }
                   }
-                  if let Some((&p, c)) = poly {
-                      return Some((p, c, min));
+                  // A polygon may stretch over several voxels, so a hit
+                  // found here may lie in one of the next voxels, behind
+                  // something that hasn't been checked yet.
+                  if let Some((p, c, b)) = poly {
+                      if space.voxel_contains((x, y, z), c) {
+                          return Some((p, c, b, min));
+                      }
                   }
               }
-              return None;
+              return poly.map(|(p, c, b)| (p, c, b, min));
 /*                  let polys : Vec<&'b Polygon> = space.grid.iter().flat_map(
                       |x| -> Vec<&'b Polygon> {
                           x.iter().flat_map(
@@ -263,60 +559,455 @@ impl Canvas {
                   */
           }
 
-    pub fn pixel_color(p: &Polygon, c: Vector3<f64>) -> RGB {
-        let v : Vec<(RGB, Vector3<f64>)> = [p.0, p.1, p.2].iter().map(
-            |x| (x.color, x.coords.into())).collect();
-
-        let colors : Vec<(RGB, f64)> = v.iter().map(
-            |&(rgb, x)| (rgb, (x - c).length())).collect();
-        let t : f64 = colors.iter().map(|&(_,t)| t).sum();
-
-        let r : f64 = colors.iter().map(|&(c, d)| 1.0-d*c.0 as f64 / t).sum();
-        let g : f64 = colors.iter().map(|&(c, d)| 1.0-d*c.1 as f64 / t).sum();
-        let b : f64 = colors.iter().map(|&(c, d)| 1.0-d*c.2 as f64 / t).sum();
-
-        RGB(r as u8, g as u8, b as u8)
-    }
-
-    pub fn render(&mut self, scene: &World) {
-        let origin = Vector3(self.width  as f64 / 2.0,
-                             self.height as f64 / 2.0,
-                             -1.0);
-        let poly = scene.shapes.iter().flat_map(|x| x.to_polygons()).collect();
-        let s = Space::new(&poly);
-        for a in 0..self.width {
-            // println!("{}", a);
-            for b in 0..self.height {
-                let dir : Vector3<f64> = Vector3(
-                    (a as f64 - self.width  as f64 / 2.0),
-                    (b as f64 - self.height as f64 / 2.0),
-                    1.0);
-                let closest = Canvas::closest_polygon(origin.into_inner(),
-                                                     dir,
-                                                     &s);
-                if let Some((p, c, d)) = closest {
-                    self.pixels[b as usize][a as usize] =
-                        Canvas::pixel_color(&p, c);
-                    self.zbuffer[b as usize][a as usize] = d
+    /// The indices of the faces between `origin` and `origin + direction`,
+    /// leaving out those near the ends.
+    fn hits(origin: Vector3<f64>, direction: Vector3<f64>, space: &Space)
+        -> Vec<usize> {
+        // A polygon may be in several voxels, but is only counted once
+        let mut hits : Vec<usize> = space.ray_boxes(origin, direction).
+            into_iter().flat_map(|(x, y, z)|
+                space.grid[x][y][z].polygons.iter().cloned()).filter(|&i|
+                match Canvas::raytrace(origin, direction,
+                                       &space.faces[i].polygon) {
+                    Some((t, _, _)) =>
+                        t > SHADOW_BIAS && t < 1.0 - SHADOW_BIAS,
+                    None => false
+                }).collect();
+        hits.sort();
+        hits.dedup();
+        hits
+    }
+
+    /// The share of light that gets from `origin` to `origin + direction`
+    /// through the surfaces in between.
+    fn transmittance(origin: Vector3<f64>, direction: Vector3<f64>,
+                     space: &Space) -> f64 {
+        Canvas::hits(origin, direction, space).iter().map(
+            |&i| 1.0 - space.faces[i].material.alpha).product()
+    }
+
+    /// Colour of a point lit by the given lights
+    ///
+    /// Every light which isn't farther than its radius from the point and
+    /// isn't obstructed by some opaque polygon adds to the brightness of the
+    /// point. The contribution of a light decreases smoothly with the
+    /// distance, so the brightness changes gradually across tiles. Colours
+    /// are in linear light.
+    fn illuminate(color: RGBf, point: Vector3<f64>, lights: &[Light],
+                  space: &Space) -> RGBf {
+        if lights.is_empty() {
+            return color;
+        }
+        let mut k = RGBf(AMBIENT, AMBIENT, AMBIENT);
+        for l in lights {
+            let to_light = l.position - point;
+            let d = to_light.length();
+            if d >= l.radius {
+                continue;
+            }
+            let through = Canvas::transmittance(point, to_light, space);
+            let f = 1.0 - (d / l.radius) * (d / l.radius);
+            k = k + l.color.to_linear() * (f * f * through);
+        }
+        color * k
+    }
+
+    /// The vertex colour at a point of a polygon, in linear light.
+    pub fn pixel_color(p: &Polygon, b: Barycentric) -> RGBf {
+        b.interpolate(p.0.color.to_linear(), p.1.color.to_linear(),
+                      p.2.color.to_linear())
+    }
+
+    fn surface_color(f: &Face, b: Barycentric) -> RGBf {
+        let color = Canvas::pixel_color(&f.polygon, b);
+        match f.material.texture {
+            Some(ref t) => {
+                let p = &f.polygon;
+                let uv = b.interpolate(p.0.uv, p.1.uv, p.2.uv);
+                t.sample(uv, f.material.sampling).to_linear() * color
+            },
+            None => color
+        }
+    }
+
+    /// The distance from a point of a face to the nearest of its edges on
+    /// the border of the shape.
+    fn border_distance(f: &Face, b: Barycentric) -> f64 {
+        let p = &f.polygon;
+        // Twice the area of the polygon
+        let area = p.normal().length();
+        let edges = [p.2.coords - p.1.coords, p.0.coords - p.2.coords,
+                     p.1.coords - p.0.coords];
+        let weights = [b.0, b.1, b.2];
+        (0 .. 3).filter(|&i| f.borders[i]).map(
+            |i| weights[i] * area / edges[i].length()).fold(
+            f64::INFINITY, f64::min)
+    }
+
+    /// The colour, the glow and the depth of the pixel at column `a` and row
+    /// `b`, in linear light
+    ///
+    /// The depth is that of the nearest surface, transparent or not.
+    fn trace_pixel(&self, a: u16, b: u16, scene: &World, camera: &Camera,
+                   space: &Space) -> (RGBf, RGBf, f64) {
+        let (forward, _, _) = camera.basis();
+        let mut random = 1 + a as u32 * self.height as u32 + b as u32;
+        let samples = self.antialiasing.samples(&mut random);
+        let mut color = RGBf(0.0, 0.0, 0.0);
+        let mut glow = RGBf(0.0, 0.0, 0.0);
+        let mut depth = f64::INFINITY;
+        for &(dx, dy) in samples.iter() {
+            let (origin, dir) = camera.ray(
+                2.0 * (a as f64 + dx) / self.width  as f64 - 1.0,
+                1.0 - 2.0 * (b as f64 + dy) / self.height as f64);
+            // Depth grows by this much per unit of distance along the ray
+            let speed = dir.dot(forward);
+            let start = camera.depth(origin);
+            let (mut near, far) = ((camera.near - start) / speed,
+                                   (camera.far  - start) / speed);
+            // Transparent surfaces are blended from the front to the back,
+            // each letting through a share of what is behind it
+            let mut through = 1.0;
+            for _ in 0 .. MAX_LAYERS {
+                let (p, c, bary, t) = match Canvas::closest_polygon(
+                    origin, dir, space, (near, far)) {
+                    Some(hit) => hit,
+                    None => break,
+                };
+                if p.xray {
+                    // Only the lines along the borders are drawn, and they
+                    // hide what is behind them
+                    let outline = p.material.outline.expect(
+                        "x-rayed face without an outline");
+                    if Canvas::border_distance(p, bary) > outline.width {
+                        near = t + SHADOW_BIAS;
+                        continue;
+                    }
+                    let line = outline.color.to_linear() * through;
+                    color = color + line;
+                    glow = glow + line * outline.glow;
+                    depth = depth.min(camera.depth(c));
+                    break;
+                }
+                let surface = Canvas::illuminate(
+                    Canvas::surface_color(p, bary), c, &scene.lighting,
+                    space) + p.material.emission.to_linear();
+                color = color + surface * (through * p.material.alpha);
+                depth = depth.min(camera.depth(c));
+                through *= 1.0 - p.material.alpha;
+                if through < OPAQUE {
+                    break;
+                }
+                near = t + SHADOW_BIAS;
+            }
+        }
+        let n = 1.0 / samples.len() as f64;
+        (color * n, glow * n, depth)
+    }
+
+    /// The boxes of the world whose look has changed since the last frame
+    ///
+    /// Those are the boxes of the shapes which were added or removed (a moved
+    /// shape is both), the reach of the lights which were added or removed,
+    /// and the reach of the lights which may cast the shadows of the changed
+    /// shapes. `None` means that the whole picture has to be traced again.
+    fn changes(&self, scene: &World, camera: &Camera,
+               shapes: &[(u64, Option<Aabb>)]) -> Option<Vec<Aabb>> {
+        let previous = match self.previous {
+            Some(ref p) if p.camera == *camera &&
+                p.antialiasing == self.antialiasing &&
+                // The ambient light only applies to lit scenes
+                p.lights.is_empty() == scene.lighting.is_empty() => p,
+            _ => return None,
+        };
+
+        let mut old : HashMap<u64, Vec<Option<Aabb>>> = HashMap::new();
+        for &(f, b) in previous.shapes.iter() {
+            old.entry(f).or_insert(vec!()).push(b);
+        }
+        let mut changed = vec!();
+        for &(f, b) in shapes.iter() {
+            match old.get_mut(&f).and_then(|v| v.pop()) {
+                Some(_) => {},
+                None => changed.extend(b),
+            }
+        }
+        for (_, v) in old {
+            changed.extend(v.into_iter().filter_map(|b| b));
+        }
+
+        let mut old_lights = previous.lights.clone();
+        let mut boxes = changed.clone();
+        for l in scene.lighting.iter() {
+            match old_lights.iter().position(|x| x == l) {
+                Some(i) => {
+                    old_lights.swap_remove(i);
+                    let reach = l.bounds();
+                    if changed.iter().any(|b| b.intersects(&reach)) {
+                        boxes.push(reach);
+                    }
+                },
+                None => boxes.push(l.bounds()),
+            }
+        }
+        boxes.extend(old_lights.iter().map(|l| l.bounds()));
+        Some(boxes)
+    }
+
+    /// Which edges of each polygon are on the border of the shape they make,
+    /// rather than shared with another polygon.
+    fn borders(polygons: &[Polygon]) -> Vec<[bool; 3]> {
+        let key = |v: &Vertex| (v.coords.0.to_bits(), v.coords.1.to_bits(),
+                                v.coords.2.to_bits());
+        let edge = |a: &Vertex, b: &Vertex| {
+            let (a, b) = (key(a), key(b));
+            if a < b { (a, b) } else { (b, a) }
+        };
+        let edges = |p: &Polygon| [edge(&p.1, &p.2), edge(&p.2, &p.0),
+                                   edge(&p.0, &p.1)];
+        let mut count = HashMap::new();
+        for p in polygons.iter() {
+            for e in edges(p).iter() {
+                *count.entry(*e).or_insert(0) += 1;
+            }
+        }
+        polygons.iter().map(|p| {
+            let e = edges(p);
+            [count[&e[0]] == 1, count[&e[1]] == 1, count[&e[2]] == 1]
+        }).collect()
+    }
+
+    /// The faces of the shapes, marking those the camera can't see.
+    fn faces(shapes: &[Shape], camera: &Camera, frustum: &Frustum,
+             stats: &mut RenderStats) -> Vec<Face> {
+        let mut faces = vec!();
+        for s in shapes.iter() {
+            let in_view = s.bounds().map_or(false, |b| frustum.intersects(&b));
+            let polygons = s.to_polygons();
+            let borders = Canvas::borders(&polygons);
+            for (p, borders) in polygons.into_iter().zip(borders) {
+                let visible = in_view && !(s.material.cull_backfaces &&
+                    camera.faces_away(p.0.coords, p.normal()));
+                if visible {
+                    stats.drawn += 1;
+                } else if in_view {
+                    stats.backfaces += 1;
+                } else {
+                    stats.shadow_casters += 1;
+                }
+                faces.push(Face {
+                    polygon: p,
+                    material: s.material.clone(),
+                    visible: visible,
+                    borders: borders,
+                    xray: false,
+                });
+            }
+        }
+        faces
+    }
+
+    /// Mark the faces with an outline which hide some part of a revealed
+    /// face from the camera, and return their bounds
+    ///
+    /// A face is taken to hide a revealed one when their projections on the
+    /// screen have overlapping bounds and it's nearer to the camera than the
+    /// farthest point of the revealed face. This may outline a face which
+    /// only comes close to a revealed one, but never misses one which hides
+    /// it. Faces which are partly behind the camera are left out.
+    fn xray(space: &mut Space, camera: &Camera) -> Vec<Aabb> {
+        // The bounds of a face on the screen, with its depths along Z
+        let projected = |p: &Polygon| {
+            let mut points = vec!();
+            for &v in [p.0, p.1, p.2].iter() {
+                match camera.project(v.coords) {
+                    Some((x, y)) =>
+                        points.push(Vector3(x, y, camera.depth(v.coords))),
+                    None => return None,
+                }
+            }
+            Aabb::from_points(points)
+        };
+        let revealed : Vec<Aabb> = space.faces.iter().filter(
+            |f| f.visible && f.material.revealed).filter_map(
+            |f| projected(&f.polygon)).collect();
+        let mut bounds = vec!();
+        for f in space.faces.iter_mut().filter(
+            |f| f.visible && f.material.outline.is_some()) {
+            let hides = projected(&f.polygon).map_or(false, |o|
+                revealed.iter().any(|r|
+                    o.min.0 < r.max.0 && r.min.0 < o.max.0 &&
+                    o.min.1 < r.max.1 && r.min.1 < o.max.1 &&
+                    o.min.2 < r.max.2 - SHADOW_BIAS));
+            if hides {
+                f.xray = true;
+                let p = &f.polygon;
+                bounds.extend(Aabb::from_points(
+                    vec!(p.0.coords, p.1.coords, p.2.coords)));
+            }
+        }
+        bounds
+    }
+
+    /// Turn the traced colours into pixels, adding the glow around the
+    /// outlines and applying the effects.
+    fn compose(&mut self, dirty: &FrameBuffer<bool>, effects: &[Effect]) {
+        let black = RGBf(0.0, 0.0, 0.0);
+        let glowing = self.glow.data().iter().any(|&c| c != black);
+        // The glow spreads beyond the traced pixels, and so did the glow
+        // which may have gone, while effects change the whole picture
+        let whole = glowing || self.glowing || !effects.is_empty() ||
+            self.effects[..] != *effects;
+        let image = if whole {
+            let mut image = self.linear.clone();
+            let halo = blur(&self.glow, GLOW_RADIUS);
+            for y in 0 .. self.height as usize {
+                for x in 0 .. self.width as usize {
+                    image[(x, y)] = image[(x, y)] + halo[(x, y)];
+                }
+            }
+            for e in effects.iter() {
+                e.apply(&mut image);
+            }
+            Some(image)
+        } else {
+            None
+        };
+        for y in 0 .. self.height as usize {
+            for x in 0 .. self.width as usize {
+                let at = (x, y);
+                match image {
+                    Some(ref i) => self.pixels[at] = i[at].to_srgb(),
+                    None if dirty[at] =>
+                        self.pixels[at] = self.linear[at].to_srgb(),
+                    None => {},
+                }
+            }
+        }
+        self.glowing = glowing;
+        self.effects = effects.to_vec();
+    }
+
+    /// Render a scene, tracing again only the pixels that may look different
+    /// from the last time.
+    ///
+    /// Shapes out of view are left out, unless they are in the reach of a
+    /// light which is in view, as they may cast shadows into it.
+    pub fn render(&mut self, scene: &World, camera: &Camera) {
+        let frustum = camera.frustum();
+        let reach : Vec<Aabb> = scene.lighting.iter().map(|l| l.bounds()).
+            filter(|b| frustum.intersects(b)).collect();
+        let (flat, culled) = scene.flatten_where(|b| frustum.intersects(b) ||
+            reach.iter().any(|r| r.intersects(b)));
+        let shapes : Vec<(u64, Option<Aabb>)> = flat.iter().map(
+            |x| (fingerprint(x), x.bounds())).collect();
+        let (w, h) = (self.width as usize, self.height as usize);
+
+        let mut boxes = self.changes(scene, camera, &shapes);
+
+        // Which faces are visible depends on the camera too
+        let reuse = self.previous.as_ref().map_or(false, |p|
+            p.camera == *camera && p.shapes.len() == shapes.len() &&
+            p.shapes.iter().zip(shapes.iter()).all(|(a, b)| a.0 == b.0));
+        let (space, xray) = match (reuse, self.previous.take()) {
+            (true, Some(p)) => (p.space, p.xray),
+            (_, previous) => {
+                let mut stats = RenderStats::default();
+                let faces = Canvas::faces(&flat, camera, &frustum, &mut stats);
+                stats.culled = culled;
+                stats.submitted = culled + faces.len();
+                self.stats = stats;
+                let mut space = Box::new(Space::new(faces));
+                let xray = Canvas::xray(&mut space, camera);
+                // The faces which have turned into outlines or back look
+                // different, even if they haven't changed
+                if let (Some(b), Some(p)) = (boxes.as_mut(), previous) {
+                    b.extend(xray.iter().filter(|x| !p.xray.contains(x)));
+                    b.extend(p.xray.iter().filter(|x| !xray.contains(x)));
+                }
+                (space, xray)
+            }
+        };
+
+        let mut dirty = FrameBuffer::new(w, h, boxes.is_none());
+        for b in boxes.iter().flat_map(|x| x.iter()) {
+            let corners : Option<Vec<(f64, f64)>> = b.corners().iter().map(
+                |&c| camera.project(c)).collect();
+            let corners = match corners {
+                Some(c) => c,
+                // Part of the box is behind the camera
+                None => {
+                    dirty.fill(true);
+                    break;
+                }
+            };
+            let xs : Vec<f64> = corners.iter().map(
+                |&(x, _)| (x + 1.0) / 2.0 * w as f64).collect();
+            let ys : Vec<f64> = corners.iter().map(
+                |&(_, y)| (1.0 - y) / 2.0 * h as f64).collect();
+            // One more pixel on each side for the rounding errors
+            let range = |cs: &[f64], n: usize| {
+                let (lo, hi) = cs.iter().fold(
+                    (f64::INFINITY, -f64::INFINITY),
+                    |(lo, hi), &c| (lo.min(c), hi.max(c)));
+                let lo = (lo.floor() - 1.0).max(0.0).min(n as f64) as usize;
+                let hi = (hi.ceil() + 1.0).max(0.0).min(n as f64) as usize;
+                lo .. hi
+            };
+            let (rx, ry) = (range(&xs, w), range(&ys, h));
+            for y in ry {
+                for x in dirty.row_mut(y)[rx.clone()].iter_mut() {
+                    *x = true;
+                }
+            }
+        }
+
+        self.stats.pixels = 0;
+        for b in 0..self.height {
+            for a in 0..self.width {
+                let at = (a as usize, b as usize);
+                if dirty[at] {
+                    self.stats.pixels += 1;
+                    let (color, glow, depth) = self.trace_pixel(
+                        a, b, scene, camera, &space);
+                    self.linear[at] = color;
+                    self.glow[at] = glow;
+                    self.zbuffer[at] = depth;
                 }
             }
         }
+        if self.stats.pixels > 0 || self.effects != scene.effects {
+            self.compose(&dirty, &scene.effects);
+        }
+        self.previous = Some(Frame {
+            space: space,
+            shapes: shapes,
+            lights: scene.lighting.clone(),
+            camera: *camera,
+            antialiasing: self.antialiasing,
+            xray: xray,
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use camera::Camera;
     use geometry::*;
-    use super::Canvas;
+    use postprocess::Effect;
+    use super::{Canvas, Antialiasing, RenderStats};
 
     #[test]
     fn raytrace_test() {
         let origin = Vector3(1, 1, 1).into_inner();
         let dir : Vector3<f64> = Vector3(-0.125, 1.0, 0.5);
         let poly = Polygon (
-            Vertex { coords: Vector3(-1.0, 8.0 , 3.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 1.0, 10.0, 2.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 0.0, 9.0 , 5.0), color: RGB(0, 0, 0) });
+            Vertex { coords: Vector3(-1.0, 8.0 , 3.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 1.0, 10.0, 2.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 0.0, 9.0 , 5.0), color: RGB(0, 0, 0),
+                     ..Default::default() });
 
         // Valid intersection
         assert!(Canvas::raytrace(origin, dir, &poly).is_some());
@@ -327,9 +1018,12 @@ mod tests {
         assert!(Canvas::raytrace(another_origin, dir, &poly).is_none());
 
         let another_poly = Polygon (
-            Vertex { coords: Vector3(-5.0, -10.0, -2.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 5.0, -12.0, -1.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 4.0, -5.0 , -6.0), color: RGB(0, 0, 0) });
+            Vertex { coords: Vector3(-5.0, -10.0, -2.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 5.0, -12.0, -1.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 4.0, -5.0 , -6.0), color: RGB(0, 0, 0),
+                     ..Default::default() });
 
         // Ray intersects the triangle behind the origin
         assert!(Canvas::raytrace(origin, dir, &another_poly).is_none());
@@ -337,13 +1031,395 @@ mod tests {
         let another_direction : Vector3<f64> = Vector3(1.0, 0.0, 0.0);
 
         let third_poly = Polygon (
-            Vertex { coords: Vector3( 5.0, 4.0, -2.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 2.0, 3.0, -2.0), color: RGB(0, 0, 0) },
-            Vertex { coords: Vector3( 4.0, 1.0, -2.0), color: RGB(0, 0, 0) });
+            Vertex { coords: Vector3( 5.0, 4.0, -2.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 2.0, 3.0, -2.0), color: RGB(0, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3( 4.0, 1.0, -2.0), color: RGB(0, 0, 0),
+                     ..Default::default() });
 
         // Ray is parallel to the triangle
         assert!(Canvas::raytrace(origin, another_direction,
                                  &third_poly).is_none());
     }
+
+    #[test]
+    fn barycentric_test() {
+        let poly = Polygon (
+            Vertex { coords: Vector3(0.0, 0.0, 2.0), color: RGB(255, 0, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3(6.0, 0.0, 2.0), color: RGB(0, 255, 0),
+                     ..Default::default() },
+            Vertex { coords: Vector3(0.0, 6.0, 2.0), color: RGB(0, 0, 255),
+                     ..Default::default() });
+        let dir : Vector3<f64> = Vector3(0.0, 0.0, 1.0);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        // The weights at a vertex select that vertex only
+        let (_, _, b) = Canvas::raytrace(Vector3(6.0, 0.0, 0.0), dir,
+                                         &poly).unwrap();
+        assert!(close(b.0, 0.0) && close(b.1, 1.0) && close(b.2, 0.0));
+
+        // The centroid has equal weights
+        let (_, p, b) = Canvas::raytrace(Vector3(2.0, 2.0, 0.0), dir,
+                                         &poly).unwrap();
+        assert!(close(b.0, 1.0 / 3.0) && close(b.1, 1.0 / 3.0) &&
+                close(b.2, 1.0 / 3.0));
+
+        // Interpolating the positions gives back the intersection point
+        let q = b.interpolate(poly.0.coords, poly.1.coords, poly.2.coords);
+        assert!(close(q.0, p.0) && close(q.1, p.1) && close(q.2, p.2));
+
+        // Colours are mixed in linear light, which is brighter than mixing
+        // sRGB values
+        let c = Canvas::pixel_color(&poly, b).to_srgb();
+        assert_eq!((c.0, c.1, c.2), (156, 156, 156));
+
+        // Bright and dark vertices don't make the colour wrap around
+        let (_, _, b) = Canvas::raytrace(Vector3(0.5, 0.5, 0.0), dir,
+                                         &poly).unwrap();
+        let c = Canvas::pixel_color(&poly, b);
+        assert!(c.0 > c.1 && c.0 > c.2 && c.0 <= 1.0);
+        assert_eq!(c.to_srgb().0, 235);
+    }
+
+    #[test]
+    fn color_clamp_test() {
+        let c = RGBf(300.0, -20.0, 127.6).to_rgb();
+        assert_eq!((c.0, c.1, c.2), (255, 0, 128));
+
+        let c = RGBf(1.5, -0.5, 0.5).to_srgb();
+        assert_eq!((c.0, c.1, c.2), (255, 0, 188));
+        for &x in [0, 1, 10, 100, 200, 255].iter() {
+            assert_eq!(RGB(x, x, x).to_linear().to_srgb().0, x);
+        }
+    }
+
+    #[test]
+    fn incremental_test() {
+        let camera = Camera::top_down(32.0, 24.0);
+        let scene = |x: f64, light: f64| {
+            let quad = |a, b, c| Shape::quad(
+                a, b, (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), c,
+                Material::default());
+            let mut shapes : Vec<Shape> = (0 .. 4).map(|i| {
+                let y = i as f64 * 6.0;
+                quad(Vector3(0.0, y, 2.0), Vector3(32.0, y + 6.0, 2.0),
+                     RGB(0x40 * i as u8, 0x80, 0xff))
+            }).collect();
+            shapes.push(quad(Vector3(x, 8.0, 1.0), Vector3(x + 4.0, 12.0, 1.0),
+                             RGB(0xff, 0xff, 0xff)));
+            World {
+                shapes: shapes,
+                nodes: vec!(),
+                effects: vec!(),
+                lighting: vec!(Light {
+                    position: Vector3(light, 12.0, 0.5),
+                    color: RGB(0xff, 0xc0, 0x80),
+                    radius: 10.0,
+                }),
+            }
+        };
+        let fresh = |world: &World| {
+            let mut canvas = Canvas::new(32, 24);
+            canvas.set_antialiasing(Antialiasing::Jittered(2));
+            canvas.render(world, &camera);
+            canvas.pixels().clone()
+        };
+
+        let mut canvas = Canvas::new(32, 24);
+        canvas.set_antialiasing(Antialiasing::Jittered(2));
+        canvas.render(&scene(4.0, 6.0), &camera);
+        // The square moves, then the light moves, then nothing does
+        for &(x, light) in [(10.0, 6.0), (10.0, 20.0), (10.0, 20.0)].iter() {
+            let world = scene(x, light);
+            canvas.render(&world, &camera);
+            assert_eq!(canvas.pixels(), &fresh(&world));
+        }
+
+        // Shapes are told apart by their looks, not by their order
+        let mut world = scene(10.0, 20.0);
+        world.shapes.reverse();
+        canvas.render(&world, &camera);
+        assert_eq!(canvas.pixels(), &fresh(&world));
+    }
+
+    #[test]
+    fn pick_test() {
+        let quad = |a, b| Shape::quad(
+            a, b, (Vector2(0.0, 0.0), Vector2(1.0, 1.0)),
+            RGB(0xff, 0xff, 0xff), Material::default());
+        let world = World {
+            shapes: vec!(quad(Vector3(0.0, 0.0, 2.0), Vector3(32.0, 24.0, 2.0)),
+                         quad(Vector3(4.0, 8.0, 1.0), Vector3(8.0, 12.0, 1.0))),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(),
+        };
+        let mut canvas = Canvas::new(32, 24);
+        assert_eq!(canvas.pick(6.0, 14.0), None);
+        canvas.render(&world, &Camera::top_down(32.0, 24.0));
+        // The nearest surface is picked, upside down from the pixels
+        assert_eq!(canvas.pick(6.0, 14.0), Some(Vector3(6.0, 10.0, 1.0)));
+        assert_eq!(canvas.pick(20.0, 4.0), Some(Vector3(20.0, 20.0, 2.0)));
+    }
+
+    #[test]
+    fn culling_test() {
+        let quad = |a, b, color, material| Shape::quad(
+            a, b, (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), color, material);
+        let mut back = quad(Vector3(2.0, 2.0, 1.0), Vector3(6.0, 4.0, 1.0),
+                            RGB(0xff, 0, 0), Material {
+                                cull_backfaces: true,
+                                ..Default::default()
+                            });
+        // Swapping the left and the right corners turns the quad around
+        back.verts.swap(0, 1);
+        back.verts.swap(2, 3);
+        let world = World {
+            shapes: vec!(
+                quad(Vector3(0.0, 0.0, 2.0), Vector3(8.0, 6.0, 2.0),
+                     RGB(0xff, 0xff, 0xff), Material::default()),
+                back,
+                quad(Vector3(100.0, 0.0, 2.0), Vector3(101.0, 1.0, 2.0),
+                     RGB(0, 0, 0xff), Material::default())),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(),
+        };
+
+        let mut canvas = Canvas::new(8, 6);
+        canvas.render(&world, &Camera::top_down(8.0, 6.0));
+        assert_eq!(canvas.pixels()[(4, 3)], RGB(0xff, 0xff, 0xff));
+        assert_eq!(canvas.stats(), RenderStats {
+            submitted: 6,
+            culled: 2,
+            shadow_casters: 0,
+            backfaces: 2,
+            drawn: 2,
+            pixels: 48,
+        });
+
+        // Nothing has changed, so nothing is traced
+        canvas.render(&world, &Camera::top_down(8.0, 6.0));
+        assert_eq!(canvas.stats().pixels, 0);
+        assert_eq!(canvas.stats().drawn, 2);
+    }
+
+    #[test]
+    fn transparency_test() {
+        let quad = |z, color, material| Shape::quad(
+            Vector3(0.0, 0.0, z), Vector3(4.0, 4.0, z),
+            (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), color, material);
+        let glass = Material { alpha: 0.5, ..Default::default() };
+        let mut world = World {
+            shapes: vec!(quad(3.0, RGB(0, 0, 0xff), Material::default()),
+                         quad(2.0, RGB(0xff, 0, 0), glass.clone()),
+                         quad(1.0, RGB(0, 0xff, 0), Material {
+                             alpha: 0.0,
+                             ..Default::default()
+                         })),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(),
+        };
+        let render = |world: &World| {
+            let mut canvas = Canvas::new(4, 4);
+            canvas.render(world, &Camera::top_down(4.0, 4.0));
+            canvas.pixels().clone()
+        };
+        // Half red and half blue, mixed in linear light
+        assert_eq!(render(&world)[(2, 2)], RGB(188, 0, 188));
+        // The order of the shapes doesn't matter
+        world.shapes.reverse();
+        assert_eq!(render(&world)[(2, 2)], RGB(188, 0, 188));
+
+        // Emissive surfaces glow with no light on them
+        let world = World {
+            shapes: vec!(quad(2.0, RGB(0, 0, 0), Material {
+                emission: RGB(0x80, 0x80, 0x80),
+                ..Default::default()
+            })),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(Light {
+                position: Vector3(100.0, 100.0, 0.0),
+                color: RGB(0xff, 0xff, 0xff),
+                radius: 1.0,
+            }),
+        };
+        assert_eq!(render(&world)[(2, 2)], RGB(0x80, 0x80, 0x80));
+
+        // Half of the light goes through the glass, so the shadow it casts
+        // around itself is lighter
+        let lit = |blocker: Material| render(&World {
+            shapes: vec!(quad(3.0, RGB(0xff, 0xff, 0xff), Material::default()),
+                         Shape::quad(Vector3(1.0, 1.0, 1.0),
+                                     Vector3(3.0, 3.0, 1.0),
+                                     (Vector2(0.0, 0.0), Vector2(1.0, 1.0)),
+                                     RGB(0, 0, 0), blocker)),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(Light {
+                position: Vector3(2.0, 2.0, 0.0),
+                color: RGB(0xff, 0xff, 0xff),
+                radius: 1000.0,
+            }),
+        })[(0, 0)];
+        let clear = lit(Material { alpha: 0.0, ..Default::default() });
+        let solid = lit(Material::default());
+        let half = lit(glass);
+        assert!(solid.0 < half.0 && half.0 < clear.0);
+    }
+
+    #[test]
+    fn falloff_test() {
+        // Two tiles of floor side by side, lit from above the first one
+        let tile = |x| Shape::quad(
+            Vector3(x, 0.0, 1.0), Vector3(x + 8.0, 4.0, 1.0),
+            (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), RGB(0xff, 0xff, 0xff),
+            Material::default());
+        let mut canvas = Canvas::new(16, 4);
+        canvas.render(&World {
+            shapes: vec!(tile(0.0), tile(8.0)),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(Light {
+                position: Vector3(2.5, 2.0, 0.0),
+                color: RGB(0x60, 0x60, 0x60),
+                radius: 15.0,
+            }),
+        }, &Camera::top_down(16.0, 4.0));
+        let row : Vec<i32> = (2 .. 16).map(
+            |x| canvas.pixels()[(x, 2)].0 as i32).collect();
+        let steps : Vec<i32> = row.windows(2).map(|w| w[0] - w[1]).collect();
+        // The light fades away from it, and no faster where the tiles meet
+        // than on either side of the border
+        assert!(steps.iter().all(|&s| s >= 0), "{:?}", row);
+        assert!(row[13] > 0 && row[0] > row[13]);
+        let border = steps[5];
+        assert!(border <= steps[4].max(steps[6]) + 1, "{:?}", row);
+    }
+
+    #[test]
+    fn xray_test() {
+        let quad = |a, b, color, material| Shape::quad(
+            a, b, (Vector2(0.0, 0.0), Vector2(1.0, 1.0)), color, material);
+        let wall = |glow| quad(Vector3(2.0, 2.0, 1.0), Vector3(6.0, 6.0, 1.0),
+                               RGB(0, 0xff, 0), Material {
+                                   outline: Some(Outline {
+                                       color: RGB(0xff, 0, 0),
+                                       width: 0.5,
+                                       glow: glow,
+                                   }),
+                                   ..Default::default()
+                               });
+        let floor = |revealed| quad(
+            Vector3(0.0, 0.0, 2.0), Vector3(8.0, 8.0, 2.0), RGB(0, 0, 0xff),
+            Material { revealed: revealed, ..Default::default() });
+        let render = |shapes| {
+            let mut canvas = Canvas::new(8, 8);
+            canvas.render(&World {
+                shapes: shapes,
+                nodes: vec!(),
+                effects: vec!(),
+                lighting: vec!(),
+            }, &Camera::top_down(8.0, 8.0));
+            canvas.pixels().clone()
+        };
+
+        // A wall in front of an ordinary floor hides it
+        let pixels = render(vec!(floor(false), wall(0.0)));
+        assert_eq!(pixels[(3, 3)], RGB(0, 0xff, 0));
+        assert_eq!(pixels[(2, 4)], RGB(0, 0xff, 0));
+
+        // The floor shows through, and the borders of the wall but not the
+        // diagonal between its triangles are drawn
+        let pixels = render(vec!(floor(true), wall(0.0)));
+        assert_eq!(pixels[(3, 3)], RGB(0, 0, 0xff));
+        assert_eq!(pixels[(3, 4)], RGB(0, 0, 0xff));
+        assert_eq!(pixels[(2, 4)], RGB(0xff, 0, 0));
+        assert_eq!(pixels[(0, 4)], RGB(0, 0, 0xff));
+
+        // The outline glows around itself
+        let pixels = render(vec!(floor(true), wall(1.0)));
+        assert!(pixels[(0, 4)].0 > 0);
+        assert!(pixels[(3, 3)].0 > 0);
+
+        // The wall turns back when what it hid moves away, even though the
+        // wall itself hasn't changed
+        let player = |x| quad(Vector3(x, 3.0, 1.5), Vector3(x + 1.0, 4.0, 1.5),
+                              RGB(0xff, 0xff, 0xff), Material {
+                                  revealed: true,
+                                  ..Default::default()
+                              });
+        let world = |x| World {
+            shapes: vec!(floor(false), wall(1.0), player(x)),
+            nodes: vec!(),
+            effects: vec!(),
+            lighting: vec!(),
+        };
+        let mut canvas = Canvas::new(8, 8);
+        canvas.render(&world(3.0), &Camera::top_down(8.0, 8.0));
+        assert_eq!(canvas.pixels()[(3, 4)], RGB(0xff, 0xff, 0xff));
+        canvas.render(&world(0.0), &Camera::top_down(8.0, 8.0));
+        let mut fresh = Canvas::new(8, 8);
+        fresh.render(&world(0.0), &Camera::top_down(8.0, 8.0));
+        assert_eq!(canvas.pixels(), fresh.pixels());
+        assert_eq!(canvas.pixels()[(2, 4)], RGB(0, 0xff, 0));
+
+        // Even a sliver of the player behind the wall is enough
+        canvas.render(&world(5.95), &Camera::top_down(8.0, 8.0));
+        assert_eq!(canvas.pixels()[(3, 3)].1, 0);
+        canvas.render(&world(6.0), &Camera::top_down(8.0, 8.0));
+        assert_eq!(canvas.pixels()[(3, 3)], RGB(0, 0xff, 0));
+    }
+
+    #[test]
+    fn effects_test() {
+        let world = |effects| World {
+            shapes: vec!(Shape::quad(
+                Vector3(0.0, 0.0, 1.0), Vector3(4.0, 4.0, 1.0),
+                (Vector2(0.0, 0.0), Vector2(1.0, 1.0)),
+                RGB(0xff, 0xff, 0xff), Material::default())),
+            nodes: vec!(),
+            lighting: vec!(),
+            effects: effects,
+        };
+        let camera = Camera::top_down(4.0, 4.0);
+        let mut canvas = Canvas::new(4, 4);
+        canvas.render(&world(vec!()), &camera);
+
+        // Effects change the picture without tracing it again
+        canvas.render(&world(vec!(Effect::Fade {
+            color: RGB(0, 0, 0),
+            amount: 1.0,
+        })), &camera);
+        assert_eq!(canvas.stats().pixels, 0);
+        assert_eq!(canvas.pixels()[(1, 1)], RGB(0, 0, 0));
+        canvas.render(&world(vec!()), &camera);
+        assert_eq!(canvas.pixels()[(1, 1)], RGB(0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn antialiasing_test() {
+        let mut random = 1;
+        assert_eq!(Antialiasing::Grid(1).samples(&mut random),
+                   vec!((0.5, 0.5)));
+        assert_eq!(Antialiasing::Grid(2).samples(&mut random),
+                   vec!((0.25, 0.25), (0.25, 0.75),
+                        (0.75, 0.25), (0.75, 0.75)));
+
+        let jittered = Antialiasing::Jittered(3).samples(&mut random);
+        assert_eq!(jittered.len(), 9);
+        // Every ray stays in its own cell of the grid
+        for (i, &(x, y)) in jittered.iter().enumerate() {
+            assert_eq!(((x * 3.0) as usize, (y * 3.0) as usize),
+                       (i / 3, i % 3));
+        }
+
+        assert_eq!("jitter:4".parse(), Ok(Antialiasing::Jittered(4)));
+        assert!("grid".parse::<Antialiasing>().is_err());
+    }
 }
 
@@ -1,7 +1,38 @@
 use tickable::Input;
-use geometry::Worldly;
+use input::Repeat;
+use bindings::Bindings;
+use geometry::{Vector3, Worldly};
 use std::fmt;
+use std::time::Duration;
 
 pub trait Screen: fmt::Debug + Worldly {
     fn tick(&mut self, Option<Input>) -> Option<Box<Screen>>;
+
+    /// Let time pass for the things which move on their own, such as
+    /// animations. The screen may then give way to another one, as with
+    /// `tick`.
+    fn advance(&mut self, _: Duration) -> Option<Box<Screen>> {
+        None
+    }
+
+    /// How the keys held down repeat their input; `None` if they don't.
+    fn repeat(&self) -> Option<Repeat> {
+        Some(Repeat::default())
+    }
+
+    /// Tell the inputs of the keys held down, the latest pressed last,
+    /// before every `advance`.
+    fn hold(&mut self, _: &[Input]) {}
+
+    /// The keys which give the inputs of the screen.
+    fn bindings(&self) -> &Bindings;
+
+    /// Take a key by its name before it's turned into an input; true if the
+    /// screen used it, as when a control is being bound to it.
+    fn key(&mut self, _: &str) -> bool {
+        false
+    }
+
+    /// Tell the point of the world a mouse click landed on.
+    fn click(&mut self, _: Vector3<f64>) {}
 }
@@ -0,0 +1,135 @@
+use framebuffer::{FrameBuffer, RowOrder};
+use geometry::RGB;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Save an image to a file
+///
+/// The format is chosen by the extension of the file: PNG for `.png`, and the
+/// raw PPM otherwise.
+pub fn save<P: AsRef<Path>>(pixels: &FrameBuffer<RGB>, path: P)
+    -> io::Result<()> {
+        let png = path.as_ref().extension().map_or(
+            false, |e| e.to_string_lossy().to_lowercase() == "png");
+        let mut out = BufWriter::new(try!(File::create(path)));
+        if png {
+            write_png(pixels, &mut out)
+        } else {
+            write_ppm(pixels, &mut out)
+        }
+    }
+
+pub fn write_ppm<W: Write>(pixels: &FrameBuffer<RGB>, out: &mut W)
+    -> io::Result<()> {
+        try!(write!(out, "P6\n{} {}\n255\n", pixels.width(),
+                    pixels.height()));
+        let mut bytes = vec!();
+        pixels.write_rgb8(&mut bytes, RowOrder::TopDown);
+        out.write_all(&bytes)
+    }
+
+/// Write an image as a PNG file
+///
+/// The image data isn't compressed: it's put into the stored blocks of a zlib
+/// stream, which every PNG reader understands.
+pub fn write_png<W: Write>(pixels: &FrameBuffer<RGB>, out: &mut W)
+    -> io::Result<()> {
+        let width = pixels.width() as u32;
+        let height = pixels.height() as u32;
+
+        // Every scanline starts with the filter type, 0 being no filter.
+        let mut raw = Vec::with_capacity(
+            height as usize * (width as usize * 3 + 1));
+        for row in pixels.rows() {
+            raw.push(0);
+            for c in row {
+                raw.extend_from_slice(&[c.0, c.1, c.2]);
+            }
+        }
+
+        let mut zlib = vec!(0x78, 0x01);
+        let mut blocks : Vec<&[u8]> = raw.chunks(0xffff).collect();
+        // The stream has to end with a final block, even if it's empty
+        if blocks.is_empty() {
+            blocks.push(&[]);
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            let len = block.len() as u16;
+            zlib.push(if i + 1 == blocks.len() { 1 } else { 0 });
+            zlib.extend_from_slice(&[len as u8, (len >> 8) as u8,
+                                     !len as u8, (!len >> 8) as u8]);
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&be32(adler32(&raw)));
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&be32(width));
+        header.extend_from_slice(&be32(height));
+        // 8 bits per channel, truecolour, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        try!(out.write_all(b"\x89PNG\r\n\x1a\n"));
+        try!(png_chunk(out, b"IHDR", &header));
+        try!(png_chunk(out, b"IDAT", &zlib));
+        png_chunk(out, b"IEND", &[])
+    }
+
+fn png_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8])
+    -> io::Result<()> {
+        try!(out.write_all(&be32(data.len() as u32)));
+        try!(out.write_all(kind));
+        try!(out.write_all(data));
+        let crc = crc32(kind.iter().chain(data.iter()));
+        out.write_all(&be32(crc))
+    }
+
+fn be32(x: u32) -> [u8; 4] {
+    [(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]
+}
+
+fn crc32<'a, I: Iterator<Item=&'a u8>>(data: I) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0 .. 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use framebuffer::FrameBuffer;
+    use geometry::RGB;
+    use super::{crc32, adler32, write_png};
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(crc32(b"IEND".iter()), 0xae426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn empty_png_test() {
+        let mut png = vec!();
+        write_png(&FrameBuffer::new(4, 0, RGB(0, 0, 0)), &mut png).unwrap();
+        // The IDAT chunk holds a zlib header, a final empty stored block and
+        // the checksum of nothing
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap();
+        assert_eq!(&png[idat - 4 .. idat], &[0, 0, 0, 11]);
+        assert_eq!(&png[idat + 4 .. idat + 15],
+                   &[0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+    }
+}
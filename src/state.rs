@@ -1,4 +1,6 @@
 use game::Game;
+use labyrinth::Movement;
+use bindings::Bindings;
 
 #[derive(Debug)]
 pub struct ScoreEntry {
@@ -25,7 +27,10 @@ pub struct ProgramState {
     pub score : Score,
     pub width : usize,
     pub height : usize,
-    pub seed : Option<u16>
+    pub seed : Option<u16>,
+    pub torch_spacing : usize,
+    pub movement : Movement,
+    pub bindings : Bindings,
 }
 
 impl ProgramState {
@@ -36,12 +41,16 @@ impl ProgramState {
             width : 70,
             height : 30,
             seed : None,
+            torch_spacing : 10,
+            movement : Movement::FourWay,
+            bindings : Bindings::default(),
         }
     }
 
     pub fn new_game(&mut self) {
         self.game = Some(Game::new(self.width, self.height,
-                                        self.seed.unwrap_or(0)))
+                                   self.seed.unwrap_or(0),
+                                   self.torch_spacing, self.movement))
     }
 }
 
@@ -0,0 +1,310 @@
+use geometry::{RGB, RGBf, Vector2};
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+/// The directory the textures are looked up in, relative to the working
+/// directory.
+pub const ASSETS_DIR: &'static str = "assets";
+
+/// The copies of the shipped assets which are used when the files can't be
+/// read.
+const EMBEDDED: [(&'static str, &'static [u8]); 3] = [
+    ("wall",  include_bytes!("../assets/wall.ppm")),
+    ("floor", include_bytes!("../assets/floor.ppm")),
+    ("pc",    include_bytes!("../assets/pc.ppm")),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sampling {
+    /// The colour of the texel the point falls into.
+    Nearest,
+    /// The weighted average of the four texels around the point.
+    Bilinear,
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextureError::Io(ref e) => write!(f, "can't read texture: {}", e),
+            TextureError::Format(ref e) => write!(f, "bad texture: {}", e),
+        }
+    }
+}
+
+impl error::Error for TextureError {
+    fn description(&self) -> &str {
+        match *self {
+            TextureError::Io(_) => "can't read texture",
+            TextureError::Format(_) => "bad texture",
+        }
+    }
+}
+
+impl From<io::Error> for TextureError {
+    fn from(e: io::Error) -> TextureError {
+        TextureError::Io(e)
+    }
+}
+
+pub struct Texture {
+    width: usize,
+    height: usize,
+    texels: Vec<RGB>,
+}
+
+impl fmt::Debug for Texture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Texture({}x{})", self.width, self.height)
+    }
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<RGB>) -> Texture {
+        assert_eq!(width * height, texels.len());
+        Texture {
+            width: width,
+            height: height,
+            texels: texels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> RGB {
+        self.texels[(y % self.height) * self.width + x % self.width]
+    }
+
+/// The texture which is shown in place of one that couldn't be loaded
+///
+/// A magenta and black checkerboard, to be easily noticed.
+    pub fn missing() -> Texture {
+        let texels = (0 .. 64).map(|i| if (i / 8 + i % 8) % 2 == 0 {
+            RGB(0xff, 0x00, 0xff)
+        } else {
+            RGB(0x00, 0x00, 0x00)
+        }).collect();
+        Texture::new(8, 8, texels)
+    }
+
+/// Read a texture from a file in the PPM format, either plain or raw.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Texture, TextureError> {
+        let mut data = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut data));
+        Texture::from_ppm(&data)
+    }
+
+/// Load a texture from the assets directory
+///
+/// Looks for `<name>.ppm` in `ASSETS_DIR`. If the file can't be loaded, the
+/// copy of the asset embedded in the program is used; if there's none,
+/// `Texture::missing` is.
+    pub fn asset(name: &str) -> Rc<Texture> {
+        let path = Path::new(ASSETS_DIR).join(format!("{}.ppm", name));
+        Rc::new(Texture::load(&path).unwrap_or_else(|_| {
+            EMBEDDED.iter().find(|&&(n, _)| n == name).and_then(
+                |&(_, data)| Texture::from_ppm(data).ok()).unwrap_or_else(
+                    Texture::missing)
+        }))
+    }
+
+    pub fn from_ppm(data: &[u8]) -> Result<Texture, TextureError> {
+        let mut pos = 0;
+        let magic = try!(ppm_token(data, &mut pos));
+        let raw = match &magic[..] {
+            b"P3" => false,
+            b"P6" => true,
+            _ => return Err(TextureError::Format(
+                    "not a PPM file".to_string())),
+        };
+        let width  = try!(ppm_number(data, &mut pos));
+        let height = try!(ppm_number(data, &mut pos));
+        let maxval = try!(ppm_number(data, &mut pos));
+        if maxval == 0 || maxval > 255 {
+            return Err(TextureError::Format(
+                    format!("unsupported maximum value {}", maxval)));
+        }
+        if width == 0 || height == 0 {
+            return Err(TextureError::Format("empty image".to_string()));
+        }
+
+        let scale = |c: usize| (c * 255 / maxval) as u8;
+        let count = try!(width.checked_mul(height).and_then(
+            |n| n.checked_mul(3)).ok_or(TextureError::Format(
+                format!("image too large: {} by {}", width, height))));
+        // Every channel takes a byte at least, so a file that is too short
+        // is rejected before anything is allocated
+        if data.len() - pos < count {
+            return Err(TextureError::Format(
+                    "not enough pixel data".to_string()));
+        }
+        let channels : Vec<usize> = if raw {
+            // A single whitespace character separates the header and the data
+            let start = pos + 1;
+            if data.len() - start < count {
+                return Err(TextureError::Format(
+                        "not enough pixel data".to_string()));
+            }
+            data[start .. start + count].iter().map(|&x| x as usize).collect()
+        } else {
+            let mut v = Vec::with_capacity(count);
+            for _ in 0 .. count {
+                v.push(try!(ppm_number(data, &mut pos)));
+            }
+            v
+        };
+        if channels.iter().any(|&c| c > maxval) {
+            return Err(TextureError::Format(
+                    "pixel value out of range".to_string()));
+        }
+
+        let texels = channels.chunks(3).map(
+            |c| RGB(scale(c[0]), scale(c[1]), scale(c[2]))).collect();
+        Ok(Texture::new(width, height, texels))
+    }
+
+/// The colour of the texture at the given point
+///
+/// The texture covers the square from (0, 0) to (1, 1) with (0, 0) being the
+/// top left corner, and is repeated outside of it.
+    pub fn sample(&self, uv: Vector2<f64>, sampling: Sampling) -> RGBf {
+        let x = uv.0 * self.width as f64;
+        let y = uv.1 * self.height as f64;
+        let wrap = |c: f64, n: usize| {
+            let n = n as i64;
+            (((c.floor() as i64) % n + n) % n) as usize
+        };
+        match sampling {
+            Sampling::Nearest =>
+                self.texel(wrap(x, self.width), wrap(y, self.height)).into(),
+            Sampling::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (fx, fy) = (x - x.floor(), y - y.floor());
+                let (x0, y0) = (wrap(x, self.width), wrap(y, self.height));
+                let (x1, y1) = ((x0 + 1) % self.width, (y0 + 1) % self.height);
+                let t = |x, y| RGBf::from(self.texel(x, y));
+                (t(x0, y0) * (1.0 - fx) + t(x1, y0) * fx) * (1.0 - fy) +
+                (t(x0, y1) * (1.0 - fx) + t(x1, y1) * fx) * fy
+            }
+        }
+    }
+}
+
+fn ppm_token(data: &[u8], pos: &mut usize) -> Result<Vec<u8>, TextureError> {
+    loop {
+        match data.get(*pos) {
+            Some(&b'#') => while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            },
+            Some(c) if (*c as char).is_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(TextureError::Format(
+                    "unexpected end of file".to_string())),
+        }
+    }
+    let start = *pos;
+    while *pos < data.len() && !(data[*pos] as char).is_whitespace() {
+        *pos += 1;
+    }
+    Ok(data[start .. *pos].to_vec())
+}
+
+fn ppm_number(data: &[u8], pos: &mut usize) -> Result<usize, TextureError> {
+    let token = try!(ppm_token(data, pos));
+    String::from_utf8_lossy(&token).parse().map_err(
+        |_| TextureError::Format(format!("expected a number, got {:?}",
+                                         String::from_utf8_lossy(&token))))
+}
+
+/// A texture split into frames of equal size, laid out left to right.
+#[derive(Clone, Debug)]
+pub struct Atlas {
+    pub texture: Rc<Texture>,
+    /// Never 0, so that every frame number wraps around to a frame.
+    frames: usize,
+}
+
+impl Atlas {
+/// An atlas of `frames` frames, which can't be none.
+    pub fn new(texture: Rc<Texture>, frames: usize) -> Atlas {
+        assert!(frames > 0, "an atlas needs at least one frame");
+        Atlas {
+            texture: texture,
+            frames: frames,
+        }
+    }
+
+/// The texture coordinates of the top left and bottom right corners of a
+/// frame.
+    pub fn frame(&self, i: usize) -> (Vector2<f64>, Vector2<f64>) {
+        let w = 1.0 / self.frames as f64;
+        let i = i % self.frames;
+        (Vector2(i as f64 * w, 0.0), Vector2((i + 1) as f64 * w, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::{RGBf, Vector2};
+    use std::rc::Rc;
+    use super::{Atlas, Texture, Sampling};
+
+    #[test]
+    fn ppm_test() {
+        let t = Texture::from_ppm(b"P3\n# comment\n2 1 15\n15 0 0  0 15 0\n").
+            unwrap();
+        assert_eq!((t.width(), t.height()), (2, 1));
+        let c = t.texel(1, 0);
+        assert_eq!((c.0, c.1, c.2), (0, 255, 0));
+
+        let t = Texture::from_ppm(b"P6 1 1 255\n\x01\x02\x03").unwrap();
+        let c = t.texel(0, 0);
+        assert_eq!((c.0, c.1, c.2), (1, 2, 3));
+
+        assert!(Texture::from_ppm(b"P6 2 2 255\n\x01\x02\x03").is_err());
+        assert!(Texture::from_ppm(b"P3 1 1 255 1 2 300").is_err());
+        assert!(Texture::from_ppm(b"GIF89a").is_err());
+        // Sizes which overflow, or which the file is far too short for
+        assert!(Texture::from_ppm(
+            b"P6 18446744073709551615 2 255\n\x01").is_err());
+        assert!(Texture::from_ppm(b"P3 100000 100000 255 1 2 3").is_err());
+    }
+
+    #[test]
+    fn sampling_test() {
+        let t = Texture::from_ppm(b"P3 2 1 255 0 0 0  200 100 0").unwrap();
+        let s = |u, sampling| t.sample(Vector2(u, 0.5), sampling);
+
+        assert_eq!(s(0.2, Sampling::Nearest), RGBf(0.0, 0.0, 0.0));
+        assert_eq!(s(0.7, Sampling::Nearest), RGBf(200.0, 100.0, 0.0));
+        // The texture repeats outside of the unit square
+        assert_eq!(s(1.7, Sampling::Nearest), RGBf(200.0, 100.0, 0.0));
+        assert_eq!(s(-0.3, Sampling::Nearest), RGBf(200.0, 100.0, 0.0));
+
+        // Texel centers are sampled exactly, the middle is the average
+        assert_eq!(s(0.25, Sampling::Bilinear), RGBf(0.0, 0.0, 0.0));
+        assert_eq!(s(0.5, Sampling::Bilinear), RGBf(100.0, 50.0, 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one frame")]
+    fn empty_atlas_test() {
+        Atlas::new(Rc::new(Texture::missing()), 0);
+    }
+}
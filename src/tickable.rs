@@ -1,6 +1,6 @@
 use direction::Direction;
 
-#[derive (Clone, Copy)]
+#[derive (Clone, Copy, PartialEq, Debug)]
 pub enum Input {
     Accept,
     Cancel,